@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "uploads" (
+    "uuid" UUID NOT NULL,
+    "uploadLength" BIGINT NOT NULL,
+    "uploadOffset" BIGINT NOT NULL DEFAULT 0,
+    "fileNameData" TEXT NOT NULL,
+    "salt" TEXT NOT NULL,
+    "iv" TEXT NOT NULL,
+    "challengeData" TEXT NOT NULL,
+    "challengeHash" TEXT NOT NULL,
+    "createdAt" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "expiresAt" timestamp with time zone NOT NULL,
+
+    CONSTRAINT "uploads_pkey" PRIMARY KEY ("uuid")
+);
@@ -0,0 +1,8 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "replicas" (
+    "ident" TEXT NOT NULL,
+    "provider" TEXT NOT NULL,
+    "createdAt" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "replicas_pkey" PRIMARY KEY ("ident", "provider")
+);
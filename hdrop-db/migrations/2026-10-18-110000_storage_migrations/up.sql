@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "storage_migrations" (
+    "source" TEXT NOT NULL,
+    "target" TEXT NOT NULL,
+    "cursorCreatedAt" timestamp with time zone,
+    "cursorUuid" UUID,
+    "migrated" BIGINT NOT NULL DEFAULT 0,
+    "retryUuids" UUID[] NOT NULL DEFAULT '{}',
+    "updatedAt" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "storage_migrations_pkey" PRIMARY KEY ("source", "target")
+);
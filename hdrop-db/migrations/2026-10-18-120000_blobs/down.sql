@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "files" DROP COLUMN "blobHash";
+
+DROP TABLE "blobs";
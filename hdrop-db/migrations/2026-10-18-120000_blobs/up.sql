@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "blobs" (
+    "hash" TEXT NOT NULL,
+    "size" BIGINT NOT NULL,
+    "refCount" BIGINT NOT NULL DEFAULT 1,
+    "createdAt" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "blobs_pkey" PRIMARY KEY ("hash")
+);
+
+ALTER TABLE "files" ADD COLUMN "blobHash" TEXT;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "uploads" DROP COLUMN "maxDownloads";
+
+ALTER TABLE "files" DROP COLUMN "downloadCount";
+ALTER TABLE "files" DROP COLUMN "maxDownloads";
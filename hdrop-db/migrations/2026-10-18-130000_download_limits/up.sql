@@ -0,0 +1,5 @@
+-- Your SQL goes here
+ALTER TABLE "files" ADD COLUMN "maxDownloads" INTEGER;
+ALTER TABLE "files" ADD COLUMN "downloadCount" INTEGER NOT NULL DEFAULT 0;
+
+ALTER TABLE "uploads" ADD COLUMN "maxDownloads" INTEGER;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE "files" ADD COLUMN "fileSize" BIGINT;
+
+ALTER TABLE "uploads" ADD COLUMN "expiry" BIGINT;
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+-- Hashed update tokens cannot be turned back into plaintext, so reverting keeps them hashed.
+-- Reverting is a no-op instead of an error, so later migrations can still be redone and reverted.
+-- Running the up migration again afterwards hashes the hashes, which locks out the owners of existing files.
+SELECT 1;
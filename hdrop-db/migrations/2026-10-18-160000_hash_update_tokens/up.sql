@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Migrations run only once, so every token is still in plaintext and gets hashed exactly once
+UPDATE "files" SET "updateToken" = encode(sha256(convert_to("updateToken", 'UTF8')), 'hex');
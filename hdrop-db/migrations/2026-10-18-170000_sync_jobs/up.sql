@@ -0,0 +1,15 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "sync_jobs" (
+    "uuid" UUID NOT NULL,
+    "ident" TEXT NOT NULL,
+    "attempts" INTEGER NOT NULL DEFAULT 0,
+    "runAt" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    "leasedUntil" timestamp with time zone,
+    "lastError" TEXT,
+    "createdAt" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "sync_jobs_pkey" PRIMARY KEY ("uuid"),
+    CONSTRAINT "sync_jobs_uuid_fkey" FOREIGN KEY ("uuid") REFERENCES "files"("uuid") ON DELETE CASCADE
+);
+
+CREATE INDEX "sync_jobs_runAt_idx" ON "sync_jobs"("runAt");
@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE "sync_jobs" DROP COLUMN IF EXISTS "traceContext";
@@ -0,0 +1,2 @@
+-- Your SQL goes here
+ALTER TABLE "sync_jobs" ADD COLUMN "traceContext" TEXT;
@@ -0,0 +1,12 @@
+-- Your SQL goes here
+CREATE TABLE IF NOT EXISTS "multipart_uploads" (
+    "provider" TEXT NOT NULL,
+    "ident" TEXT NOT NULL,
+    "uploadId" TEXT NOT NULL,
+    "partSize" BIGINT NOT NULL,
+    "partNumbers" INTEGER[] NOT NULL DEFAULT '{}',
+    "etags" TEXT[] NOT NULL DEFAULT '{}',
+    "updatedAt" timestamp with time zone NOT NULL DEFAULT CURRENT_TIMESTAMP,
+
+    CONSTRAINT "multipart_uploads_pkey" PRIMARY KEY ("provider", "ident")
+);
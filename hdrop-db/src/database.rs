@@ -1,9 +1,9 @@
 use std::borrow::Cow;
 
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
 use deadpool_diesel::postgres::{Manager, Pool};
-use diesel::prelude::*;
+use diesel::{pg::PgConnection, prelude::*};
 use hdrop_shared::{
     metrics::{names, UpdateMetrics},
     responses,
@@ -12,9 +12,27 @@ use uuid::Uuid;
 
 use crate::{
     error::Result,
-    models::{File, InsertFile},
-    schema::files::dsl as files_table,
-    utils::{TokenGenerator, UPDATE_TOKEN_LENGTH},
+    models::{
+        Blob,
+        File,
+        InsertFile,
+        InsertUpload,
+        MultipartUpload,
+        Replica,
+        StorageMigration,
+        SyncJob,
+        Upload,
+    },
+    schema::{
+        blobs::dsl as blobs_table,
+        files::dsl as files_table,
+        multipart_uploads::dsl as multipart_uploads_table,
+        replicas::dsl as replicas_table,
+        storage_migrations::dsl as storage_migrations_table,
+        sync_jobs::dsl as sync_jobs_table,
+        uploads::dsl as uploads_table,
+    },
+    utils::TokenGenerator,
 };
 
 pub struct Database {
@@ -28,19 +46,24 @@ impl Database {
         let database_url = hdrop_shared::env::database_url()?;
         let manager = Manager::new(database_url, deadpool_diesel::Runtime::Tokio1);
         let pool = Pool::builder(manager).max_size(8).build()?;
-        let generator = TokenGenerator::default();
+        let generator = TokenGenerator::try_from_env()?;
         Ok(Database { pool, generator })
     }
 
+    /// Inserts a file under a newly generated access token, the access token of the given file is ignored.
+    /// Takes over the resumable upload with the same UUID, see [Database::take_over_upload].
     pub async fn insert_file(&self, file: InsertFile) -> Result<File> {
+        let generator = self.generator.clone();
         let r = Ok(self
             .pool
             .get()
             .await?
-            .interact(|conn| {
-                diesel::insert_into(files_table::files)
-                    .values(file)
-                    .get_result::<File>(conn)
+            .interact(move |conn| {
+                conn.transaction(|conn| {
+                    let file = Self::insert_with_access_token(conn, &generator, file)?;
+                    Self::take_over_upload(conn, file.uuid)?;
+                    Ok::<_, diesel::result::Error>(file)
+                })
             })
             .await??);
         // Action-based update of metrics
@@ -62,6 +85,16 @@ impl Database {
             .await??)
     }
 
+    /// Check that a connection can be established and queries are answered.
+    pub async fn ping(&self) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(|conn| diesel::sql_query("SELECT 1").execute(conn).map(|_| ()))
+            .await??)
+    }
+
     pub async fn get_file_rows(&self) -> Result<i64> {
         Ok(self
             .pool
@@ -104,6 +137,83 @@ impl Database {
             .await??)
     }
 
+    pub async fn update_max_downloads(&self, uuid: Uuid, max_downloads: Option<i32>) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::update(files_table::files.filter(files_table::uuid.eq(uuid)))
+                    .set(files_table::maxDownloads.eq(max_downloads))
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Counts a download of the file, unless its download limit is already reached.
+    /// Once the limit is reached, the file expires after `grace_period` at the latest.
+    /// Returns the updated file, or `None` if no downloads are left.
+    pub async fn register_download(
+        &self,
+        uuid: Uuid,
+        grace_period: chrono::Duration,
+    ) -> Result<Option<File>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                conn.transaction(|conn| {
+                    let file = diesel::update(
+                        files_table::files
+                            .filter(files_table::uuid.eq(uuid))
+                            .filter(
+                                files_table::maxDownloads
+                                    .is_null()
+                                    .or(files_table::downloadCount
+                                        .nullable()
+                                        .lt(files_table::maxDownloads)),
+                            ),
+                    )
+                    .set(files_table::downloadCount.eq(files_table::downloadCount + 1))
+                    .get_result::<File>(conn)
+                    .optional()?;
+
+                    let Some(mut file) = file else {
+                        return Ok(None);
+                    };
+
+                    let deadline = Utc::now() + grace_period;
+                    if file.remaining_downloads() == Some(0) && file.expiresAt > deadline {
+                        diesel::update(files_table::files.filter(files_table::uuid.eq(uuid)))
+                            .set(files_table::expiresAt.eq(deadline))
+                            .execute(conn)?;
+                        file.expiresAt = deadline;
+                    }
+
+                    Ok::<_, diesel::result::Error>(Some(file))
+                })
+            })
+            .await??)
+    }
+
+    /// Counts a failed attempt to solve the challenge of the file.
+    /// Returns the number of failed attempts so far.
+    pub async fn register_failed_challenge(&self, uuid: Uuid) -> Result<i32> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::update(files_table::files.filter(files_table::uuid.eq(uuid)))
+                    .set(files_table::failedChallenges.eq(files_table::failedChallenges + 1))
+                    .returning(files_table::failedChallenges)
+                    .get_result(conn)
+            })
+            .await??)
+    }
+
     pub async fn get_file_by_uuid(&self, uuid: Uuid) -> Result<File> {
         Ok(self
             .pool
@@ -176,6 +286,7 @@ impl Database {
         let file = self.get_file_by_access_token(access_token).await?;
 
         Ok(responses::GetChallengeData {
+            remaining_downloads: file.remaining_downloads(),
             salt: file.salt,
             iv: file.iv,
             challenge: file.challengeData,
@@ -210,46 +321,732 @@ impl Database {
             .await??)
     }
 
-    pub async fn check_access_token_collission<'a>(
+    /// Inserts a file whose contents are stored in a deduplicated blob and references the blob.
+    /// The file gets a newly generated access token and takes over its upload, like in [Database::insert_file].
+    /// Returns the file and whether the blob is new, i.e. has to be stored.
+    pub async fn insert_file_with_blob(&self, file: InsertFile, size: i64) -> Result<(File, bool)> {
+        let hash = file.blobHash.clone().unwrap_or_default();
+        let blob = Blob {
+            hash,
+            size,
+            refCount: 1,
+            createdAt: Utc::now(),
+        };
+
+        let generator = self.generator.clone();
+        let r = Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                conn.transaction(|conn| {
+                    let ref_count = diesel::insert_into(blobs_table::blobs)
+                        .values(blob)
+                        .on_conflict(blobs_table::hash)
+                        .do_update()
+                        .set(blobs_table::refCount.eq(blobs_table::refCount + 1))
+                        .returning(blobs_table::refCount)
+                        .get_result::<i64>(conn)?;
+
+                    let file = Self::insert_with_access_token(conn, &generator, file)?;
+                    Self::take_over_upload(conn, file.uuid)?;
+
+                    Ok::<_, diesel::result::Error>((file, ref_count == 1))
+                })
+            })
+            .await??);
+        // Action-based update of metrics
+        self.update_metrics().await;
+        r
+    }
+
+    /// Deletes a deduplicated file and drops its reference to the blob.
+    /// Returns the number of remaining references.
+    pub async fn delete_file_and_release_blob(&self, uuid: Uuid, hash: String) -> Result<i64> {
+        let r = Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                conn.transaction(|conn| {
+                    diesel::delete(files_table::files.filter(files_table::uuid.eq(uuid)))
+                        .execute(conn)?;
+
+                    diesel::update(blobs_table::blobs.filter(blobs_table::hash.eq(hash)))
+                        .set(blobs_table::refCount.eq(blobs_table::refCount - 1))
+                        .returning(blobs_table::refCount)
+                        .get_result::<i64>(conn)
+                })
+            })
+            .await??);
+        // Action-based update of metrics
+        self.update_metrics().await;
+        r
+    }
+
+    /// Returns blobs which are no longer referenced by any file.
+    pub async fn get_unreferenced_blobs(&self) -> Result<Vec<String>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                blobs_table::blobs
+                    .filter(blobs_table::refCount.le(0))
+                    .select(blobs_table::hash)
+                    .load::<String>(conn)
+            })
+            .await??)
+    }
+
+    /// Deletes a blob, unless it got referenced again in the meantime.
+    /// Returns the deleted blob.
+    pub async fn delete_blob_if_unreferenced(&self, hash: String) -> Result<Option<Blob>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::delete(
+                    blobs_table::blobs
+                        .filter(blobs_table::hash.eq(hash))
+                        .filter(blobs_table::refCount.le(0)),
+                )
+                .get_result::<Blob>(conn)
+                .optional()
+            })
+            .await??)
+    }
+
+    /// Re-inserts a deleted blob, e.g. if its contents could not be deleted.
+    pub async fn restore_blob(&self, blob: Blob) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::insert_into(blobs_table::blobs)
+                    .values(blob)
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    pub async fn insert_upload(&self, upload: InsertUpload) -> Result<Upload> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(|conn| {
+                diesel::insert_into(uploads_table::uploads)
+                    .values(upload)
+                    .get_result::<Upload>(conn)
+            })
+            .await??)
+    }
+
+    pub async fn get_upload(&self, uuid: Uuid) -> Result<Upload> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                uploads_table::uploads
+                    .filter(uploads_table::uuid.eq(uuid))
+                    .first(conn)
+            })
+            .await??)
+    }
+
+    /// Moves the offset of an upload forward.
+    /// Only succeeds if the stored offset still equals `expected_offset`, returns whether the offset was updated.
+    pub async fn update_upload_offset(
         &self,
-        access_token: impl Into<Cow<'a, str>>,
+        uuid: Uuid,
+        expected_offset: i64,
+        new_offset: i64,
     ) -> Result<bool> {
-        let access_token = access_token.into().into_owned();
         Ok(self
             .pool
             .get()
             .await?
-            .interact(|conn| {
+            .interact(move |conn| {
+                diesel::update(
+                    uploads_table::uploads
+                        .filter(uploads_table::uuid.eq(uuid))
+                        .filter(uploads_table::uploadOffset.eq(expected_offset)),
+                )
+                .set(uploads_table::uploadOffset.eq(new_offset))
+                .execute(conn)
+                .map(|rows| rows == 1)
+            })
+            .await??)
+    }
+
+    pub async fn delete_upload(&self, uuid: Uuid) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::delete(uploads_table::uploads.filter(uploads_table::uuid.eq(uuid)))
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    pub async fn get_uploads_to_flush(&self) -> Result<Vec<Uuid>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                uploads_table::uploads
+                    .filter(uploads_table::expiresAt.lt(Utc::now()))
+                    .select(uploads_table::uuid)
+                    .load::<Uuid>(conn)
+            })
+            .await??)
+    }
+
+    /// Records that the given storage providers hold a copy of a stored object.
+    pub async fn insert_replicas(&self, ident: String, providers: Vec<String>) -> Result<()> {
+        let time = Utc::now();
+        let replicas = providers
+            .into_iter()
+            .map(|provider| Replica {
+                ident: ident.clone(),
+                provider,
+                createdAt: time,
+            })
+            .collect::<Vec<_>>();
+
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::insert_into(replicas_table::replicas)
+                    .values(replicas)
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Returns the storage providers holding a copy of a stored object.
+    pub async fn get_replica_providers(&self, ident: String) -> Result<Vec<String>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                replicas_table::replicas
+                    .filter(replicas_table::ident.eq(ident))
+                    .select(replicas_table::provider)
+                    .load::<String>(conn)
+            })
+            .await??)
+    }
+
+    pub async fn delete_replica(&self, ident: String, provider: String) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::delete(
+                    replicas_table::replicas
+                        .filter(replicas_table::ident.eq(ident))
+                        .filter(replicas_table::provider.eq(provider)),
+                )
+                .execute(conn)
+                .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Returns objects which are stored on fewer than `provider_count` of the given providers.
+    pub async fn get_incomplete_replicas(
+        &self,
+        providers: Vec<String>,
+        limit: i64,
+    ) -> Result<Vec<String>> {
+        let provider_count = providers.len() as i64;
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                replicas_table::replicas
+                    .filter(replicas_table::provider.eq_any(providers))
+                    .group_by(replicas_table::ident)
+                    .having(diesel::dsl::count_star().lt(provider_count))
+                    .select(replicas_table::ident)
+                    .limit(limit)
+                    .load::<String>(conn)
+            })
+            .await??)
+    }
+
+    /// Returns a page of files, newest first.
+    pub async fn get_files(&self, offset: i64, limit: i64) -> Result<Vec<File>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
                 files_table::files
-                    .filter(files_table::accessToken.eq(access_token))
-                    .first::<File>(conn)
-                    .is_ok()
+                    .order((files_table::createdAt.desc(), files_table::uuid))
+                    .offset(offset)
+                    .limit(limit)
+                    .load::<File>(conn)
             })
-            .await?)
+            .await??)
     }
-    /// Generates access token with min length.
-    /// Retriess generation when collissions happen (10 times), after that it increases the generated length by 1.
-    pub async fn generate_access_token(&self) -> Result<String> {
-        let mut target_length = self.generator.get_access_token_min_length();
-        let mut access_token = TokenGenerator::generate_token(target_length);
+
+    /// Returns files in creation order, starting after the given cursor.
+    pub async fn get_files_after(
+        &self,
+        cursor: Option<(DateTime<Utc>, Uuid)>,
+        limit: i64,
+    ) -> Result<Vec<File>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                let mut query = files_table::files
+                    .order((files_table::createdAt, files_table::uuid))
+                    .limit(limit)
+                    .into_boxed();
+
+                if let Some((created_at, uuid)) = cursor {
+                    query = query.filter(
+                        files_table::createdAt
+                            .gt(created_at)
+                            .or(files_table::createdAt
+                                .eq(created_at)
+                                .and(files_table::uuid.gt(uuid))),
+                    );
+                }
+
+                query.load::<File>(conn)
+            })
+            .await??)
+    }
+
+    pub async fn get_storage_migration(
+        &self,
+        source: String,
+        target: String,
+    ) -> Result<Option<StorageMigration>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                storage_migrations_table::storage_migrations
+                    .filter(storage_migrations_table::source.eq(source))
+                    .filter(storage_migrations_table::target.eq(target))
+                    .first(conn)
+                    .optional()
+            })
+            .await??)
+    }
+
+    /// Returns the progress of all storage migrations.
+    pub async fn get_storage_migrations(&self) -> Result<Vec<StorageMigration>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                storage_migrations_table::storage_migrations
+                    .order(storage_migrations_table::updatedAt.desc())
+                    .load::<StorageMigration>(conn)
+            })
+            .await??)
+    }
+
+    /// Inserts or updates the progress of a storage migration.
+    pub async fn save_storage_migration(&self, migration: StorageMigration) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::insert_into(storage_migrations_table::storage_migrations)
+                    .values(&migration)
+                    .on_conflict((
+                        storage_migrations_table::source,
+                        storage_migrations_table::target,
+                    ))
+                    .do_update()
+                    .set(&migration)
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    pub async fn delete_storage_migration(&self, source: String, target: String) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::delete(
+                    storage_migrations_table::storage_migrations
+                        .filter(storage_migrations_table::source.eq(source))
+                        .filter(storage_migrations_table::target.eq(target)),
+                )
+                .execute(conn)
+                .map(|_| ())
+            })
+            .await??)
+    }
+
+    pub async fn get_multipart_upload(
+        &self,
+        provider: String,
+        ident: String,
+    ) -> Result<Option<MultipartUpload>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                multipart_uploads_table::multipart_uploads
+                    .filter(multipart_uploads_table::provider.eq(provider))
+                    .filter(multipart_uploads_table::ident.eq(ident))
+                    .first(conn)
+                    .optional()
+            })
+            .await??)
+    }
+
+    /// Returns the unfinished multipart uploads of a storage provider.
+    pub async fn get_multipart_uploads(&self, provider: String) -> Result<Vec<MultipartUpload>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                multipart_uploads_table::multipart_uploads
+                    .filter(multipart_uploads_table::provider.eq(provider))
+                    .load::<MultipartUpload>(conn)
+            })
+            .await??)
+    }
+
+    /// Inserts or updates the state of a multipart upload.
+    pub async fn save_multipart_upload(&self, upload: MultipartUpload) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::insert_into(multipart_uploads_table::multipart_uploads)
+                    .values(&upload)
+                    .on_conflict((
+                        multipart_uploads_table::provider,
+                        multipart_uploads_table::ident,
+                    ))
+                    .do_update()
+                    .set(&upload)
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    pub async fn delete_multipart_upload(&self, provider: String, ident: String) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::delete(
+                    multipart_uploads_table::multipart_uploads
+                        .filter(multipart_uploads_table::provider.eq(provider))
+                        .filter(multipart_uploads_table::ident.eq(ident)),
+                )
+                .execute(conn)
+                .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Queues the file for synchronization to the storage provider.
+    pub async fn insert_sync_job(
+        &self,
+        uuid: Uuid,
+        ident: String,
+        trace_context: Option<String>,
+    ) -> Result<()> {
+        let time = Utc::now();
+        let job = SyncJob {
+            uuid,
+            ident,
+            attempts: 0,
+            runAt: time,
+            leasedUntil: None,
+            lastError: None,
+            createdAt: time,
+            traceContext: trace_context,
+        };
+
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::insert_into(sync_jobs_table::sync_jobs)
+                    .values(job)
+                    .on_conflict_do_nothing()
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Claims due sync jobs which are not leased by another worker, leasing them for the given duration.
+    /// Jobs of crashed workers become available again once their lease runs out.
+    pub async fn claim_sync_jobs(
+        &self,
+        limit: i64,
+        lease: chrono::Duration,
+    ) -> Result<Vec<SyncJob>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                conn.transaction(|conn| {
+                    let now = Utc::now();
+                    let uuids = sync_jobs_table::sync_jobs
+                        .filter(sync_jobs_table::runAt.le(now))
+                        .filter(
+                            sync_jobs_table::leasedUntil
+                                .is_null()
+                                .or(sync_jobs_table::leasedUntil.lt(now)),
+                        )
+                        .order(sync_jobs_table::runAt)
+                        .limit(limit)
+                        .select(sync_jobs_table::uuid)
+                        .for_update()
+                        .skip_locked()
+                        .load::<Uuid>(conn)?;
+
+                    diesel::update(
+                        sync_jobs_table::sync_jobs.filter(sync_jobs_table::uuid.eq_any(uuids)),
+                    )
+                    .set(sync_jobs_table::leasedUntil.eq(now + lease))
+                    .get_results::<SyncJob>(conn)
+                })
+            })
+            .await??)
+    }
+
+    /// Extends the lease of a running sync job, so no other worker claims it meanwhile.
+    pub async fn renew_sync_job_lease(&self, uuid: Uuid, lease: chrono::Duration) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::update(sync_jobs_table::sync_jobs.find(uuid))
+                    .set(sync_jobs_table::leasedUntil.eq(Utc::now() + lease))
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Removes a finished sync job.
+    pub async fn complete_sync_job(&self, uuid: Uuid) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::delete(sync_jobs_table::sync_jobs.filter(sync_jobs_table::uuid.eq(uuid)))
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Releases a failed sync job and schedules its next attempt.
+    pub async fn retry_sync_job(
+        &self,
+        uuid: Uuid,
+        run_at: DateTime<Utc>,
+        error: String,
+    ) -> Result<()> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                diesel::update(sync_jobs_table::sync_jobs.filter(sync_jobs_table::uuid.eq(uuid)))
+                    .set((
+                        sync_jobs_table::attempts.eq(sync_jobs_table::attempts + 1),
+                        sync_jobs_table::runAt.eq(run_at),
+                        sync_jobs_table::leasedUntil.eq(None::<DateTime<Utc>>),
+                        sync_jobs_table::lastError.eq(error),
+                    ))
+                    .execute(conn)
+                    .map(|_| ())
+            })
+            .await??)
+    }
+
+    /// Returns the number of queued sync jobs and the creation time of the oldest one.
+    pub async fn get_sync_queue_stats(&self) -> Result<(i64, Option<DateTime<Utc>>)> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                sync_jobs_table::sync_jobs
+                    .select((
+                        diesel::dsl::count_star(),
+                        diesel::dsl::min(sync_jobs_table::createdAt),
+                    ))
+                    .first(conn)
+            })
+            .await??)
+    }
+
+    /// Returns queued sync jobs in order of their next attempt.
+    pub async fn get_sync_jobs(&self, offset: i64, limit: i64) -> Result<Vec<SyncJob>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                sync_jobs_table::sync_jobs
+                    .order((sync_jobs_table::runAt, sync_jobs_table::uuid))
+                    .offset(offset)
+                    .limit(limit)
+                    .load::<SyncJob>(conn)
+            })
+            .await??)
+    }
+
+    /// Returns the queued sync jobs of the given files.
+    pub async fn get_sync_jobs_by_uuid(&self, uuids: Vec<Uuid>) -> Result<Vec<SyncJob>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                sync_jobs_table::sync_jobs
+                    .filter(sync_jobs_table::uuid.eq_any(uuids))
+                    .load::<SyncJob>(conn)
+            })
+            .await??)
+    }
+
+    /// Returns the UUIDs of all queued sync jobs.
+    pub async fn get_sync_job_uuids(&self) -> Result<Vec<Uuid>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                sync_jobs_table::sync_jobs
+                    .select(sync_jobs_table::uuid)
+                    .load::<Uuid>(conn)
+            })
+            .await??)
+    }
+
+    /// Returns the UUIDs of all unfinished resumable uploads.
+    pub async fn get_upload_uuids(&self) -> Result<Vec<Uuid>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                uploads_table::uploads
+                    .select(uploads_table::uuid)
+                    .load::<Uuid>(conn)
+            })
+            .await??)
+    }
+
+    /// Returns the hashes of all deduplicated blobs, including unreferenced ones awaiting deletion.
+    pub async fn get_blob_hashes(&self) -> Result<Vec<String>> {
+        Ok(self
+            .pool
+            .get()
+            .await?
+            .interact(move |conn| {
+                blobs_table::blobs
+                    .select(blobs_table::hash)
+                    .load::<String>(conn)
+            })
+            .await??)
+    }
+
+    /// Inserts the file with generated access tokens until one does not collide with an existing file.
+    /// Collisions are detected atomically by the unique index on the access token, so concurrent inserts never share a token.
+    /// Colliding inserts do nothing instead of failing, which keeps an enclosing transaction usable.
+    /// Retries generation when collisions happen (10 times), after that it increases the generated length by 1.
+    fn insert_with_access_token(
+        conn: &mut PgConnection,
+        generator: &TokenGenerator,
+        mut file: InsertFile,
+    ) -> QueryResult<File> {
+        let mut target_length = generator.get_access_token_min_length();
         let mut collisions = 0;
 
-        while self.check_access_token_collission(&access_token).await? {
-            collisions += 1;
+        loop {
+            file.accessToken = generator.generate_token(target_length);
+            let inserted = diesel::insert_into(files_table::files)
+                .values(&file)
+                .on_conflict(files_table::accessToken)
+                .do_nothing()
+                .get_result::<File>(conn)
+                .optional()?;
 
+            if let Some(file) = inserted {
+                return Ok(file);
+            }
+
+            collisions += 1;
             if collisions > 10 {
                 target_length += 1;
                 // From now on, only two repeat attempts before increasing the length again
                 collisions = 8;
             }
-            access_token = TokenGenerator::generate_token(target_length);
         }
+    }
+
+    /// Removes the resumable upload a file was finalized from, if there is one.
+    /// Runs in the transaction inserting the file, so the upload is only gone once the file exists.
+    /// The expiration worker then never deletes the spool file of a stored file, and a failed insert can be retried.
+    fn take_over_upload(conn: &mut PgConnection, uuid: Uuid) -> QueryResult<()> {
+        diesel::delete(uploads_table::uploads.filter(uploads_table::uuid.eq(uuid)))
+            .execute(conn)
+            .map(|_| ())
+    }
 
-        Ok(access_token)
+    pub fn generate_update_token(&self) -> String {
+        self.generator
+            .generate_token(self.generator.get_update_token_length())
     }
 
-    pub fn generate_update_token() -> String {
-        TokenGenerator::generate_token(UPDATE_TOKEN_LENGTH)
+    /// Hash of an update token, which is stored instead of the token itself.
+    pub fn hash_update_token(update_token: &str) -> String {
+        TokenGenerator::hash_token(update_token)
     }
 }
 
@@ -21,6 +21,8 @@ pub enum Error {
     ),
     #[error("{0}")]
     DeadpoolInteract(#[from] deadpool_diesel::postgres::InteractError),
+    #[error("Invalid token policy: {0}")]
+    InvalidTokenPolicy(String),
 }
 
 impl Error {
@@ -6,5 +6,15 @@ mod utils;
 pub mod error;
 pub use self::{
     database::Database,
-    models::{File, InsertFile},
+    models::{
+        Blob,
+        File,
+        InsertFile,
+        InsertUpload,
+        MultipartUpload,
+        Replica,
+        StorageMigration,
+        SyncJob,
+        Upload,
+    },
 };
@@ -13,6 +13,7 @@ use serde::{Deserialize, Serialize};
 pub struct File {
     pub uuid: Uuid,
     pub accessToken: String,
+    /// SHA-256 hash of the update token.
     pub updateToken: String,
     pub dataUrl: Option<String>,
     pub fileNameData: String,
@@ -22,6 +23,42 @@ pub struct File {
     pub expiresAt: DateTime<Utc>,
     pub challengeData: String,
     pub challengeHash: String,
+    /// Hash of the deduplicated blob holding the file contents.
+    pub blobHash: Option<String>,
+    /// Number of downloads after which the file gets deleted.
+    pub maxDownloads: Option<i32>,
+    pub downloadCount: i32,
+    /// Size of the file contents in bytes, unknown for files uploaded before it was recorded.
+    pub fileSize: Option<i64>,
+    /// Number of failed attempts to solve the challenge of the file.
+    pub failedChallenges: i32,
+}
+
+impl File {
+    /// Identifier of the file contents in the storage provider.
+    /// Deduplicated files are stored under the hash of their blob, all others under their UUID.
+    pub fn storage_ident(&self) -> String {
+        self.blobHash
+            .clone()
+            .unwrap_or_else(|| self.uuid.to_string())
+    }
+
+    /// Whether the ident has the format of a [File::storage_ident], a UUID or a hex-encoded SHA-256 hash.
+    /// Tells objects stored by hdrop apart from unrelated ones, e.g. in a shared bucket.
+    pub fn is_storage_ident(ident: &str) -> bool {
+        let is_uuid = Uuid::parse_str(ident).is_ok_and(|uuid| uuid.to_string() == ident);
+        let is_hash = ident.len() == 64
+            && ident
+                .bytes()
+                .all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'));
+        is_uuid || is_hash
+    }
+
+    /// Number of downloads left before the file gets deleted, `None` if unlimited.
+    pub fn remaining_downloads(&self) -> Option<u32> {
+        self.maxDownloads
+            .map(|max_downloads| max_downloads.saturating_sub(self.downloadCount).max(0) as u32)
+    }
 }
 
 #[derive(Default, Serialize, Deserialize, Debug, Insertable, Identifiable, AsChangeset)]
@@ -33,6 +70,7 @@ pub struct InsertFile {
     #[diesel(deserialize_as = i32)]
     pub uuid: Uuid,
     pub accessToken: String,
+    /// SHA-256 hash of the update token.
     pub updateToken: String,
     pub dataUrl: Option<String>,
     pub fileNameData: String,
@@ -42,4 +80,132 @@ pub struct InsertFile {
     pub expiresAt: DateTime<Utc>,
     pub challengeData: String,
     pub challengeHash: String,
+    /// Hash of the deduplicated blob holding the file contents.
+    pub blobHash: Option<String>,
+    pub maxDownloads: Option<i32>,
+    pub fileSize: Option<i64>,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Queryable, Selectable, Identifiable)]
+#[diesel(primary_key(uuid))]
+#[diesel(table_name = crate::schema::uploads)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(non_snake_case)]
+pub struct Upload {
+    pub uuid: Uuid,
+    pub uploadLength: i64,
+    pub uploadOffset: i64,
+    pub fileNameData: String,
+    pub salt: String,
+    pub iv: String,
+    pub challengeData: String,
+    pub challengeHash: String,
+    pub createdAt: DateTime<Utc>,
+    pub expiresAt: DateTime<Utc>,
+    pub maxDownloads: Option<i32>,
+    /// Requested expiry of the finished file in seconds.
+    pub expiry: Option<i64>,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Insertable)]
+#[diesel(table_name = crate::schema::uploads)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(non_snake_case)]
+pub struct InsertUpload {
+    pub uuid: Uuid,
+    pub uploadLength: i64,
+    pub uploadOffset: i64,
+    pub fileNameData: String,
+    pub salt: String,
+    pub iv: String,
+    pub challengeData: String,
+    pub challengeHash: String,
+    pub createdAt: DateTime<Utc>,
+    pub expiresAt: DateTime<Utc>,
+    pub maxDownloads: Option<i32>,
+    pub expiry: Option<i64>,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::replicas)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(non_snake_case)]
+pub struct Replica {
+    pub ident: String,
+    pub provider: String,
+    pub createdAt: DateTime<Utc>,
+}
+
+#[derive(
+    Default, Serialize, Deserialize, Debug, Clone, Queryable, Selectable, Insertable, AsChangeset,
+)]
+#[diesel(primary_key(source, target))]
+#[diesel(table_name = crate::schema::storage_migrations)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(non_snake_case)]
+pub struct StorageMigration {
+    pub source: String,
+    pub target: String,
+    /// Creation time of the last processed file.
+    pub cursorCreatedAt: Option<DateTime<Utc>>,
+    /// UUID of the last processed file.
+    pub cursorUuid: Option<Uuid>,
+    pub migrated: i64,
+    /// Files which could not be migrated yet, because they failed or were not stored on the source yet.
+    pub retryUuids: Vec<Uuid>,
+    pub updatedAt: DateTime<Utc>,
+}
+
+#[derive(Default, Serialize, Deserialize, Debug, Queryable, Selectable, Insertable)]
+#[diesel(table_name = crate::schema::blobs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(non_snake_case)]
+pub struct Blob {
+    pub hash: String,
+    pub size: i64,
+    /// Number of files referencing the blob.
+    pub refCount: i64,
+    pub createdAt: DateTime<Utc>,
+}
+
+/// File waiting to be synchronized from the spool to the storage provider.
+#[derive(Default, Serialize, Deserialize, Debug, Queryable, Selectable, Insertable)]
+#[diesel(primary_key(uuid))]
+#[diesel(table_name = crate::schema::sync_jobs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(non_snake_case)]
+pub struct SyncJob {
+    pub uuid: Uuid,
+    /// Identifier of the file contents in the storage provider, see [File::storage_ident].
+    pub ident: String,
+    /// Number of failed attempts so far.
+    pub attempts: i32,
+    /// Time of the next attempt.
+    pub runAt: DateTime<Utc>,
+    /// Time until which a worker holds the job, after which other workers may claim it.
+    pub leasedUntil: Option<DateTime<Utc>>,
+    pub lastError: Option<String>,
+    pub createdAt: DateTime<Utc>,
+    /// W3C `traceparent` of the request which queued the job, linking its attempts to the upload.
+    pub traceContext: Option<String>,
+}
+
+/// Unfinished multipart upload to a storage provider, kept so it can be resumed after a failure or restart.
+#[derive(
+    Default, Serialize, Deserialize, Debug, Clone, Queryable, Selectable, Insertable, AsChangeset,
+)]
+#[diesel(primary_key(provider, ident))]
+#[diesel(table_name = crate::schema::multipart_uploads)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(non_snake_case)]
+pub struct MultipartUpload {
+    /// Name of the storage provider as configured in `STORAGE_PROVIDER`, e.g. `s3@backup`.
+    pub provider: String,
+    pub ident: String,
+    pub uploadId: String,
+    pub partSize: i64,
+    /// Numbers of the uploaded parts, in the order of their `etags`.
+    pub partNumbers: Vec<i32>,
+    pub etags: Vec<String>,
+    pub updatedAt: DateTime<Utc>,
 }
@@ -1,5 +1,14 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    blobs (hash) {
+        hash -> Text,
+        size -> Int8,
+        refCount -> Int8,
+        createdAt -> Timestamptz,
+    }
+}
+
 diesel::table! {
     files (uuid) {
         uuid -> Uuid,
@@ -13,5 +22,84 @@ diesel::table! {
         expiresAt -> Timestamptz,
         challengeData -> Text,
         challengeHash -> Text,
+        blobHash -> Nullable<Text>,
+        maxDownloads -> Nullable<Int4>,
+        downloadCount -> Int4,
+        fileSize -> Nullable<Int8>,
+        failedChallenges -> Int4,
+    }
+}
+
+diesel::table! {
+    multipart_uploads (provider, ident) {
+        provider -> Text,
+        ident -> Text,
+        uploadId -> Text,
+        partSize -> Int8,
+        partNumbers -> Array<Int4>,
+        etags -> Array<Text>,
+        updatedAt -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    replicas (ident, provider) {
+        ident -> Text,
+        provider -> Text,
+        createdAt -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    storage_migrations (source, target) {
+        source -> Text,
+        target -> Text,
+        cursorCreatedAt -> Nullable<Timestamptz>,
+        cursorUuid -> Nullable<Uuid>,
+        migrated -> Int8,
+        retryUuids -> Array<Uuid>,
+        updatedAt -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    sync_jobs (uuid) {
+        uuid -> Uuid,
+        ident -> Text,
+        attempts -> Int4,
+        runAt -> Timestamptz,
+        leasedUntil -> Nullable<Timestamptz>,
+        lastError -> Nullable<Text>,
+        createdAt -> Timestamptz,
+        traceContext -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    uploads (uuid) {
+        uuid -> Uuid,
+        uploadLength -> Int8,
+        uploadOffset -> Int8,
+        fileNameData -> Text,
+        salt -> Text,
+        iv -> Text,
+        challengeData -> Text,
+        challengeHash -> Text,
+        createdAt -> Timestamptz,
+        expiresAt -> Timestamptz,
+        maxDownloads -> Nullable<Int4>,
+        expiry -> Nullable<Int8>,
     }
 }
+
+diesel::joinable!(sync_jobs -> files (uuid));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    blobs,
+    files,
+    multipart_uploads,
+    replicas,
+    storage_migrations,
+    sync_jobs,
+    uploads,
+);
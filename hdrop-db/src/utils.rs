@@ -1,34 +1,186 @@
-use sha3::{Digest, Sha3_256};
-use uuid::Uuid;
+use rand::{rngs::OsRng, seq::SliceRandom, thread_rng, Rng};
+use sha2::{Digest, Sha256};
 
-pub const UPDATE_TOKEN_LENGTH: usize = 8;
+use crate::error::{Error, Result};
+
+/// Minimum length of access tokens, unless configured otherwise.
+const DEFAULT_ACCESS_TOKEN_MIN_LENGTH: usize = 5;
+/// Length of update tokens, unless configured otherwise.
+/// Update tokens are stored as unsalted hashes, so they need enough entropy (128 bits as hex) to resist brute force.
+const DEFAULT_UPDATE_TOKEN_LENGTH: usize = 32;
+/// Minimum number of words of access tokens made of words, unless configured otherwise.
+const DEFAULT_ACCESS_TOKEN_MIN_WORDS: usize = 3;
+/// Number of words of update tokens made of words, unless configured otherwise.
+const DEFAULT_UPDATE_TOKEN_WORDS: usize = 16;
+
+const HEX: &[u8] = b"0123456789abcdef";
+/// Base58 leaves out characters which are easily confused, like `0`, `O`, `I` and `l`.
+const BASE58: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE62: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+
+/// Characters or words tokens are made of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alphabet {
+    Hex,
+    Base58,
+    Base62,
+    /// Short English words separated by `-`, e.g. `lake-gold-pony`. The token length is the number of words.
+    Words,
+}
+
+impl Alphabet {
+    fn parse(alphabet: &str) -> Result<Self> {
+        match alphabet.to_lowercase().as_str() {
+            "hex" => Ok(Self::Hex),
+            "base58" => Ok(Self::Base58),
+            "base62" => Ok(Self::Base62),
+            "words" => Ok(Self::Words),
+            _ => Err(Error::InvalidTokenPolicy(format!(
+                "Unknown token alphabet '{alphabet}'"
+            ))),
+        }
+    }
+}
+
+/// Source of the randomness tokens are generated from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntropySource {
+    /// The random number generator of the operating system, queried for every token.
+    Os,
+    /// A cryptographically secure generator per thread, periodically reseeded from the operating system.
+    Thread,
+}
+
+impl EntropySource {
+    fn parse(source: &str) -> Result<Self> {
+        match source.to_lowercase().as_str() {
+            "os" => Ok(Self::Os),
+            "thread" => Ok(Self::Thread),
+            _ => Err(Error::InvalidTokenPolicy(format!(
+                "Unknown token entropy source '{source}'"
+            ))),
+        }
+    }
+}
 
 /// Access- and Update token generator.
-#[derive(Debug)]
+/// Configured via the `TOKEN_ALPHABET`, `TOKEN_ENTROPY_SOURCE`, `ACCESS_TOKEN_MIN_LENGTH` and `UPDATE_TOKEN_LENGTH` environment variables.
+#[derive(Debug, Clone)]
 pub struct TokenGenerator {
+    alphabet: Alphabet,
+    entropy_source: EntropySource,
     access_token_min_length: usize,
+    update_token_length: usize,
 }
 
 impl Default for TokenGenerator {
     fn default() -> Self {
         TokenGenerator {
-            access_token_min_length: 5,
+            alphabet: Alphabet::Hex,
+            entropy_source: EntropySource::Os,
+            access_token_min_length: DEFAULT_ACCESS_TOKEN_MIN_LENGTH,
+            update_token_length: DEFAULT_UPDATE_TOKEN_LENGTH,
         }
     }
 }
 
 impl TokenGenerator {
+    pub fn try_from_env() -> Result<Self> {
+        let alphabet = match hdrop_shared::env::token_alphabet() {
+            Ok(alphabet) => Alphabet::parse(&alphabet)?,
+            Err(_) => Alphabet::Hex,
+        };
+        let entropy_source = match hdrop_shared::env::token_entropy_source() {
+            Ok(source) => EntropySource::parse(&source)?,
+            Err(_) => EntropySource::Os,
+        };
+
+        // Words carry more entropy than characters, so fewer of them are needed
+        let (access_token_min_length, update_token_length) = match alphabet {
+            Alphabet::Words => (DEFAULT_ACCESS_TOKEN_MIN_WORDS, DEFAULT_UPDATE_TOKEN_WORDS),
+            _ => (DEFAULT_ACCESS_TOKEN_MIN_LENGTH, DEFAULT_UPDATE_TOKEN_LENGTH),
+        };
+        let access_token_min_length =
+            hdrop_shared::env::access_token_min_length().unwrap_or(access_token_min_length);
+        let update_token_length =
+            hdrop_shared::env::update_token_length().unwrap_or(update_token_length);
+
+        if access_token_min_length == 0 || update_token_length == 0 {
+            return Err(Error::InvalidTokenPolicy(
+                "Token lengths must be at least 1".to_string(),
+            ));
+        }
+
+        Ok(TokenGenerator {
+            alphabet,
+            entropy_source,
+            access_token_min_length,
+            update_token_length,
+        })
+    }
+
     pub fn get_access_token_min_length(&self) -> usize {
         self.access_token_min_length
     }
 
-    /// Generates a SHA3(uuidv4) as String truncated to the given length.
-    pub fn generate_token(length: usize) -> String {
-        let uuid = Uuid::new_v4();
-        let mut hasher = Sha3_256::new();
-        hasher.update(uuid);
-        let result = hasher.finalize();
-        let result = format!("{:x}", result);
-        result.chars().take(length).collect::<String>()
+    pub fn get_update_token_length(&self) -> usize {
+        self.update_token_length
+    }
+
+    /// Generates a random token of the given length, drawing uniformly from the alphabet.
+    pub fn generate_token(&self, length: usize) -> String {
+        match self.entropy_source {
+            EntropySource::Os => self.generate_token_with(&mut OsRng, length),
+            EntropySource::Thread => self.generate_token_with(&mut thread_rng(), length),
+        }
+    }
+
+    fn generate_token_with<R: Rng>(&self, rng: &mut R, length: usize) -> String {
+        let characters = match self.alphabet {
+            Alphabet::Hex => HEX,
+            Alphabet::Base58 => BASE58,
+            Alphabet::Base62 => BASE62,
+            Alphabet::Words => {
+                return (0..length)
+                    .filter_map(|_| WORDS.choose(rng).copied())
+                    .collect::<Vec<_>>()
+                    .join("-")
+            }
+        };
+
+        (0..length)
+            .filter_map(|_| characters.choose(rng).map(|c| *c as char))
+            .collect()
+    }
+
+    /// Hex-encoded SHA-256 hash of a token, for tokens which are not stored in plaintext.
+    pub fn hash_token(token: &str) -> String {
+        format!("{:x}", Sha256::digest(token.as_bytes()))
     }
 }
+
+/// Words of [Alphabet::Words], 256 of them, so each word adds 8 bits of entropy.
+const WORDS: [&str; 256] = [
+    "able", "acid", "aged", "also", "area", "army", "away", "baby", "back", "ball", "band", "bank",
+    "base", "bath", "bear", "beat", "bell", "belt", "best", "bird", "blue", "boat", "body", "bold",
+    "bone", "book", "born", "boss", "bowl", "bulk", "burn", "bush", "busy", "cafe", "cake", "calm",
+    "camp", "card", "care", "cart", "case", "cash", "cast", "cell", "chef", "chip", "city", "clay",
+    "club", "coal", "coat", "code", "cold", "cook", "cool", "copy", "core", "corn", "cost", "crew",
+    "crop", "cube", "cute", "dark", "data", "dawn", "deal", "deep", "deer", "desk", "dial", "diet",
+    "dish", "dock", "door", "dose", "down", "draw", "drum", "duck", "dust", "duty", "east", "easy",
+    "echo", "edge", "epic", "even", "exit", "face", "fact", "fair", "farm", "fast", "fern", "file",
+    "film", "fine", "fire", "firm", "fish", "flag", "flat", "flow", "foam", "fog", "fold", "folk",
+    "food", "foot", "fork", "form", "fort", "fox", "free", "frog", "fuel", "full", "fund", "gain",
+    "game", "gate", "gear", "gift", "girl", "glad", "glow", "goal", "gold", "golf", "good", "gown",
+    "grid", "grow", "gulf", "hair", "half", "hall", "hand", "harp", "hawk", "head", "heat", "herb",
+    "hero", "high", "hill", "hint", "home", "hood", "hook", "hope", "horn", "host", "hour", "huge",
+    "hunt", "idea", "inch", "iron", "item", "jazz", "join", "joke", "jump", "jury", "keen", "kept",
+    "kind", "king", "kite", "knee", "knot", "lake", "lamp", "land", "lane", "last", "lava", "lawn",
+    "leaf", "left", "lens", "life", "lift", "lime", "line", "lion", "list", "loaf", "lock", "loft",
+    "long", "loop", "lord", "love", "luck", "lung", "mail", "main", "make", "mall", "many", "maps",
+    "mask", "meal", "mild", "milk", "mill", "mind", "mint", "mist", "mode", "moon", "moss", "most",
+    "move", "much", "mule", "myth", "nail", "name", "navy", "neat", "neck", "nest", "news", "next",
+    "nice", "node", "noon", "nose", "note", "oak", "oath", "ocean", "odd", "oil", "open", "oval",
+    "oven", "pack", "page", "pair", "palm", "park", "path", "peak", "pear", "pier", "pine", "pipe",
+    "plan", "play", "plum", "poem",
+];
@@ -1,5 +1,7 @@
 pub mod expiration_worker;
 pub mod metrics_updater;
+pub mod reconciliation_worker;
+pub mod storage_migrator;
 pub mod storage_synchronizer;
 
 pub use self::metrics_updater::{metrics_middleware, MetricsUpdater};
@@ -1,28 +1,40 @@
 use std::{sync::Arc, time::Duration};
 
 use hdrop_db::Database;
+use hdrop_shared::metrics::names;
 use tokio::sync::RwLock;
 use tracing::instrument;
 use uuid::Uuid;
 
-use crate::{core::StorageProvider, error::Error, server::CacheVariant, Result};
+use crate::{
+    core::StorageProvider,
+    error::Error,
+    server::{CacheVariant, Spool},
+    Result,
+};
 
 pub struct ExpirationWorker {
-    provider: Arc<RwLock<Box<dyn StorageProvider + Sync + Send>>>,
+    provider: Arc<dyn StorageProvider + Sync + Send>,
     database: Arc<Database>,
     cache: Arc<RwLock<CacheVariant>>,
+    spool: Arc<Spool>,
+    blob_lock: Arc<RwLock<()>>,
 }
 
 impl ExpirationWorker {
     pub fn new(
-        provider: Arc<RwLock<Box<dyn StorageProvider + Sync + Send>>>,
+        provider: Arc<dyn StorageProvider + Sync + Send>,
         database: Arc<Database>,
         cache: Arc<RwLock<CacheVariant>>,
+        spool: Arc<Spool>,
+        blob_lock: Arc<RwLock<()>>,
     ) -> Self {
         Self {
             provider,
             database,
             cache,
+            spool,
+            blob_lock,
         }
     }
 
@@ -33,6 +45,13 @@ impl ExpirationWorker {
             .unwrap_or_else(|_| Vec::with_capacity(0))
     }
 
+    pub async fn get_expired_uploads(&self) -> Vec<Uuid> {
+        self.database
+            .get_uploads_to_flush()
+            .await
+            .unwrap_or_else(|_| Vec::with_capacity(0))
+    }
+
     async fn delete_file_from_database(&self, uuid: Uuid) -> Result<()> {
         if let Err(err) = self.database.delete_file_by_uuid(uuid).await {
             tracing::error!("Could not delete file from database: {err}");
@@ -45,12 +64,7 @@ impl ExpirationWorker {
 
     async fn delete_file_from_provider(&self, uuid: Uuid) -> Result<()> {
         // Check if file exists in storage provider
-        let result = self
-            .provider
-            .read()
-            .await
-            .file_exists(uuid.to_string())
-            .await;
+        let result = self.provider.file_exists(uuid.to_string()).await;
 
         // Match result
         match result {
@@ -66,12 +80,7 @@ impl ExpirationWorker {
         }
 
         // Delete file from storage provider
-        let result = self
-            .provider
-            .write()
-            .await
-            .delete_file(uuid.to_string())
-            .await;
+        let result = self.provider.delete_file(uuid.to_string()).await;
 
         // Match result
         match result {
@@ -87,6 +96,15 @@ impl ExpirationWorker {
     }
 
     pub async fn delete_file(&self, file: Uuid) -> Result<()> {
+        let blob_hash = match self.database.get_file_by_uuid(file).await {
+            Ok(file) => file.blobHash,
+            Err(err) if err.is_not_found() => None,
+            Err(err) => {
+                tracing::error!("Could not load file from database: {err}");
+                return Err(Error::Database(err));
+            }
+        };
+
         // Check if file exists in cache
         let mut cache_error: bool = false;
         if self.cache.read().await.exists(file) {
@@ -101,14 +119,40 @@ impl ExpirationWorker {
             tracing::debug!("File not found in cache");
         }
 
-        // Delete file from provider
-        if self.delete_file_from_provider(file).await.is_ok() {
-            // Delete file from database
-            if self.delete_file_from_database(file).await.is_err() {
-                return Err(Error::DatabaseDeletion);
+        // Delete file from spool, in case it has not been synchronized yet
+        if let Err(err) = self.spool.delete(file).await {
+            tracing::error!("Could not delete file from spool: {err}");
+            cache_error = true;
+        }
+
+        match blob_hash {
+            // Deduplicated contents are only deleted once the last file referencing them is gone
+            Some(hash) => match self
+                .database
+                .delete_file_and_release_blob(file, hash.clone())
+                .await
+            {
+                Ok(0) => {
+                    // Failures are retried by the next sweep
+                    let _ = self.delete_blob(hash).await;
+                }
+                Ok(_) => tracing::trace!("File deleted from database: {file}"),
+                Err(err) => {
+                    tracing::error!("Could not delete file from database: {err}");
+                    return Err(Error::DatabaseDeletion);
+                }
+            },
+            // Delete file from provider
+            None => {
+                if self.delete_file_from_provider(file).await.is_ok() {
+                    // Delete file from database
+                    if self.delete_file_from_database(file).await.is_err() {
+                        return Err(Error::DatabaseDeletion);
+                    }
+                } else {
+                    return Err(Error::ProviderDeletion);
+                }
             }
-        } else {
-            return Err(Error::ProviderDeletion);
         }
 
         if cache_error {
@@ -118,6 +162,56 @@ impl ExpirationWorker {
         }
     }
 
+    /// Delete the contents of a deduplicated blob which is no longer referenced.
+    pub async fn delete_blob(&self, hash: String) -> Result<()> {
+        // Holding the blob lock keeps a new upload of the same contents from being stored meanwhile
+        let _blob_lock = self.blob_lock.write().await;
+
+        let Some(blob) = self
+            .database
+            .delete_blob_if_unreferenced(hash.clone())
+            .await?
+        else {
+            tracing::debug!("Blob {hash} got referenced again, keeping it");
+            return Ok(());
+        };
+
+        let result = match self.provider.file_exists(hash.clone()).await {
+            Ok(true) => self.provider.delete_file(hash.clone()).await,
+            Ok(false) => Ok(()),
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(()) => {
+                tracing::trace!("Blob deleted from StorageProvider: {hash}");
+                Ok(())
+            }
+            Err(err) => {
+                tracing::error!("Could not delete blob from StorageProvider: {err}");
+                // Keep track of the blob, so the deletion is retried
+                self.database.restore_blob(blob).await?;
+                Err(Error::ProviderDeletion)
+            }
+        }
+    }
+
+    /// Delete an unfinished resumable upload.
+    pub async fn delete_upload(&self, upload: Uuid) -> Result<()> {
+        if let Err(err) = self.spool.delete(upload).await {
+            tracing::error!("Could not delete upload from spool: {err}");
+            return Err(err);
+        }
+
+        if let Err(err) = self.database.delete_upload(upload).await {
+            tracing::error!("Could not delete upload from database: {err}");
+            return Err(Error::DatabaseDeletion);
+        }
+
+        tracing::trace!("Upload deleted: {upload}");
+        Ok(())
+    }
+
     pub async fn sweep(&self) {
         // Get expired files from database
         let files = self.get_expired_files().await;
@@ -128,9 +222,41 @@ impl ExpirationWorker {
         }
 
         // Iterate over expired files
+        let mut expired = 0;
         for file in files {
             // Error cases get ignored in background workers
-            let _ = self.delete_file(file).await;
+            if self.delete_file(file).await.is_ok() {
+                expired += 1;
+            }
+        }
+        metrics::counter!(names::expiration::FILES_EXPIRED_TOTAL, expired);
+        metrics::gauge!(names::expiration::LAST_SWEEP_FILES_EXPIRED, expired as f64);
+
+        // Get abandoned uploads from database
+        let uploads = self.get_expired_uploads().await;
+
+        if !uploads.is_empty() {
+            tracing::trace!("Found {} unfinished uploads to be deleted", uploads.len());
+        }
+
+        for upload in uploads {
+            let _ = self.delete_upload(upload).await;
+        }
+
+        // Retry deletions of blobs whose last reference is gone
+        let blobs = self
+            .database
+            .get_unreferenced_blobs()
+            .await
+            .unwrap_or_else(|_| Vec::with_capacity(0));
+
+        for blob in blobs {
+            let _ = self.delete_blob(blob).await;
+        }
+
+        // Clean up leftovers of the storage provider
+        if let Err(err) = self.provider.cleanup().await {
+            tracing::warn!("Storage provider cleanup failed: {err}");
         }
     }
 
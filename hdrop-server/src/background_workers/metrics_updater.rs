@@ -1,37 +1,142 @@
-use hdrop_shared::metrics::names;
+use std::{path::PathBuf, time::Duration};
 
-use crate::core::monitoring::SystemMetrics;
+use hdrop_shared::{env, metrics::names};
+use tokio::time::{interval, MissedTickBehavior};
+
+use crate::{
+    core::{monitoring::SystemMetrics, provider_names_from_env, storage_dir_from_env},
+    server::{CacheVariant, Spool},
+};
+
+/// Time between two updates of the system metrics, unless configured otherwise.
+const DEFAULT_INTERVAL_SECS: u64 = 15;
 
 pub struct MetricsUpdater {
     system: SystemMetrics,
+    /// Directories whose disk usage is monitored.
+    directories: Vec<PathBuf>,
+    interval: Duration,
 }
 
 impl MetricsUpdater {
     pub fn new() -> Self {
+        let interval_secs = env::metrics_interval_secs()
+            .unwrap_or(DEFAULT_INTERVAL_SECS)
+            .max(1);
+
         Self {
             system: SystemMetrics::new(),
+            directories: Self::directories_from_env(),
+            interval: Duration::from_secs(interval_secs),
         }
     }
 
-    /// Time-based update of all metrics except for the self updating ones (requests)
-    pub async fn run(mut self) {
-        loop {
-            // Update RAM
-            let ram_status = self.system.ram_status();
+    /// Cache, spool and local storage directories.
+    fn directories_from_env() -> Vec<PathBuf> {
+        let mut directories = vec![CacheVariant::dir_from_env(), Spool::dir_from_env()];
+        let providers = provider_names_from_env().unwrap_or_default();
+        directories.extend(
+            providers
+                .iter()
+                .filter_map(|name| storage_dir_from_env(name)),
+        );
+        directories.dedup();
+        directories
+    }
+
+    fn update_system(&mut self) {
+        // Update RAM
+        let ram_status = self.system.ram_status();
+
+        metrics::gauge!(names::system::RAM_USAGE_B, ram_status.used() as f64);
+
+        // Update CPU
+        let cpu_status = self.system.cpu_status();
+        let len = cpu_status.len().max(1) as f64;
+        let added_up_usage: f64 = cpu_status.iter().map(|cpu| cpu.utilization() * 100.).sum();
+
+        metrics::gauge!(names::system::AVG_CPU_USAGE, added_up_usage / len);
+    }
+
+    fn update_network(&mut self) {
+        for network in self.system.network_status() {
+            let labels = [("interface", network.interface)];
+            metrics::gauge!(
+                names::network::NETWORK_OCTETS_RECEIVED,
+                network.received as f64,
+                &labels
+            );
+            metrics::gauge!(
+                names::network::NETWORK_OCTETS_TRANSMITTED,
+                network.transmitted as f64,
+                &labels
+            );
+        }
+    }
 
-            metrics::gauge!(names::system::RAM_USAGE_B, ram_status.used() as f64);
+    fn update_disks(&mut self) {
+        for directory in &self.directories {
+            // Directories of unused cache strategies do not exist
+            let Some(disk_status) = self.system.disk_status(directory) else {
+                continue;
+            };
+
+            let labels = [("path", directory.display().to_string())];
+            metrics::gauge!(
+                names::system::DISK_TOTAL_B,
+                disk_status.total() as f64,
+                &labels
+            );
+            metrics::gauge!(
+                names::system::DISK_USED_B,
+                disk_status.used() as f64,
+                &labels
+            );
+        }
+    }
+
+    fn update_process(&mut self) {
+        let Some(process_status) = self.system.process_status() else {
+            return;
+        };
+
+        metrics::gauge!(
+            names::process::RESIDENT_MEMORY_B,
+            process_status.resident_memory as f64
+        );
+        if let Some(open_fds) = process_status.open_fds {
+            metrics::gauge!(names::process::OPEN_FDS, open_fds as f64);
+        }
+    }
 
-            // Update CPU
-            let cpu_status = self.system.cpu_status();
-            let len = cpu_status.len() as f64;
-            let mut added_up_usage = 0.;
-            for cpu in cpu_status {
-                added_up_usage += cpu.utilization();
-            }
+    /// Runtime metrics are only available if built with `--cfg tokio_unstable`.
+    #[cfg(tokio_unstable)]
+    fn update_runtime(&self) {
+        let runtime = tokio::runtime::Handle::current().metrics();
 
-            let average = added_up_usage / len;
+        metrics::gauge!(names::process::TOKIO_WORKERS, runtime.num_workers() as f64);
+        metrics::gauge!(
+            names::process::TOKIO_ALIVE_TASKS,
+            runtime.active_tasks_count() as f64
+        );
+    }
+
+    #[cfg(not(tokio_unstable))]
+    fn update_runtime(&self) {}
+
+    /// Time-based update of all metrics except for the self updating ones (requests)
+    pub async fn run(mut self) {
+        let mut interval = interval(self.interval);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            interval.tick().await;
 
-            metrics::gauge!(names::system::AVG_CPU_USAGE, average);
+            self.update_system();
+            self.update_network();
+            self.update_disks();
+            self.update_process();
+            self.update_runtime();
         }
     }
 }
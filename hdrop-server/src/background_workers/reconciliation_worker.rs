@@ -0,0 +1,403 @@
+use std::{collections::HashSet, convert::Infallible, sync::Arc, time::Duration};
+
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures::stream;
+use hdrop_db::{Database, File};
+use hdrop_shared::{env, metrics::names};
+use tokio::sync::{Notify, RwLock};
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{
+    core::StorageProvider,
+    error::Error,
+    server::{CacheVariant, Spool},
+    telemetry,
+    Result,
+};
+
+/// Number of files loaded from the database at once.
+const BATCH_SIZE: i64 = 500;
+/// Time between two reconciliations, unless configured otherwise.
+const DEFAULT_INTERVAL_MINUTES: u64 = 1440;
+/// Spool files modified within this time may still be written and are never considered orphaned.
+const SPOOL_GRACE_PERIOD: Duration = Duration::from_secs(3600);
+
+/// Decides how the [ReconciliationWorker] handles inconsistencies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReconciliationPolicy {
+    /// Only report inconsistencies.
+    Report,
+    /// Synchronize files which are not stored yet and delete orphaned spool files.
+    /// Orphaned provider objects are only deleted if `RECONCILIATION_DELETE_ORPHANS` is set.
+    Repair,
+    /// Like [ReconciliationPolicy::Repair], additionally expires files whose contents are lost.
+    Purge,
+}
+
+impl ReconciliationPolicy {
+    pub fn try_from_env() -> Result<Self> {
+        match env::reconciliation_policy().map(|s| s.to_lowercase()) {
+            Ok(ref policy) if policy == "report" => Ok(Self::Report),
+            Ok(ref policy) if policy == "repair" => Ok(Self::Repair),
+            Ok(ref policy) if policy == "purge" => Ok(Self::Purge),
+            Ok(policy) => Err(Error::InvalidReconciliationPolicy(policy)),
+            // Deleting data is opt-in
+            Err(_) => Ok(Self::Report),
+        }
+    }
+
+    fn repairs(&self) -> bool {
+        *self != Self::Report
+    }
+}
+
+/// Inconsistency between the database, the cache, the spool and the storage provider.
+#[derive(Debug, Clone, Copy)]
+enum Finding {
+    /// Provider object not referenced by any file or blob.
+    OrphanedObject,
+    /// Spool file belonging to neither a file nor an upload.
+    OrphanedSpoolFile,
+    /// Cache entry of a file which is already stored. Files are only cached until they are stored.
+    StaleCacheEntry,
+    /// File whose contents are neither stored nor queued for synchronization, but still in the spool or cache.
+    UnsyncedFile,
+    /// File whose contents are nowhere to be found.
+    MissingContent,
+}
+
+impl Finding {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::OrphanedObject => "orphaned_object",
+            Self::OrphanedSpoolFile => "orphaned_spool_file",
+            Self::StaleCacheEntry => "stale_cache_entry",
+            Self::UnsyncedFile => "unsynced_file",
+            Self::MissingContent => "missing_content",
+        }
+    }
+}
+
+/// Action taken by the [ReconciliationWorker] to resolve a [Finding].
+#[derive(Debug, Clone, Copy)]
+enum Action {
+    Deleted,
+    Requeued,
+    Expired,
+}
+
+impl Action {
+    fn label(&self) -> &'static str {
+        match self {
+            Self::Deleted => "deleted",
+            Self::Requeued => "requeued",
+            Self::Expired => "expired",
+        }
+    }
+}
+
+/// Result of a single reconciliation.
+#[derive(Debug, Default)]
+struct Report {
+    orphaned_objects: usize,
+    orphaned_spool_files: usize,
+    stale_cache_entries: usize,
+    unsynced_files: usize,
+    missing_contents: usize,
+    resolved: usize,
+}
+
+impl Report {
+    fn record(&mut self, finding: Finding) {
+        metrics::increment_counter!(names::reconciliation::FINDINGS_TOTAL, "kind" => finding.label());
+        match finding {
+            Finding::OrphanedObject => self.orphaned_objects += 1,
+            Finding::OrphanedSpoolFile => self.orphaned_spool_files += 1,
+            Finding::StaleCacheEntry => self.stale_cache_entries += 1,
+            Finding::UnsyncedFile => self.unsynced_files += 1,
+            Finding::MissingContent => self.missing_contents += 1,
+        }
+    }
+
+    fn resolve(&mut self, action: Action) {
+        metrics::increment_counter!(names::reconciliation::ACTIONS_TOTAL, "action" => action.label());
+        self.resolved += 1;
+    }
+}
+
+/// Checks that files in the database, the spool, the cache and the storage provider agree.
+///
+/// Runs at startup and periodically afterwards. What is done about inconsistencies is decided by the [ReconciliationPolicy].
+pub struct ReconciliationWorker {
+    provider: Arc<dyn StorageProvider + Sync + Send>,
+    database: Arc<Database>,
+    cache: Arc<RwLock<CacheVariant>>,
+    spool: Arc<Spool>,
+    sync_notify: Arc<Notify>,
+    policy: ReconciliationPolicy,
+    /// Whether orphaned provider objects are deleted, which is opt-in apart from the policy.
+    delete_orphans: bool,
+    interval: Option<Duration>,
+}
+
+impl ReconciliationWorker {
+    pub fn try_new(
+        provider: Arc<dyn StorageProvider + Sync + Send>,
+        database: Arc<Database>,
+        cache: Arc<RwLock<CacheVariant>>,
+        spool: Arc<Spool>,
+        sync_notify: Arc<Notify>,
+    ) -> Result<Self> {
+        // An interval of 0 only reconciles at startup
+        let interval_minutes =
+            env::reconciliation_interval_minutes().unwrap_or(DEFAULT_INTERVAL_MINUTES);
+
+        Ok(Self {
+            provider,
+            database,
+            cache,
+            spool,
+            sync_notify,
+            policy: ReconciliationPolicy::try_from_env()?,
+            delete_orphans: env::reconciliation_delete_orphans().unwrap_or(false),
+            interval: (interval_minutes > 0).then(|| Duration::from_secs(interval_minutes * 60)),
+        })
+    }
+
+    /// Queue the file for synchronization, restoring its contents from the cache if the spool lost them.
+    async fn requeue(&self, file: &File) -> Result<()> {
+        if !self.spool.exists(file.uuid).await {
+            let content = self.cache.read().await.get(file.uuid).await?.to_vec();
+            let content = stream::iter([Ok::<Bytes, Infallible>(content.into())]);
+            self.spool.write(file.uuid, u64::MAX, content).await?;
+        }
+
+        self.database
+            .insert_sync_job(
+                file.uuid,
+                file.storage_ident(),
+                telemetry::current_context(),
+            )
+            .await?;
+        self.sync_notify.notify_one();
+        Ok(())
+    }
+
+    /// Delete the cache entry of a stored file, left over by a failed deletion after its synchronization.
+    async fn reconcile_cache_entry(&self, file: &File, report: &mut Report) {
+        if !self.cache.read().await.exists(file.uuid) {
+            return;
+        }
+
+        tracing::warn!("File {} is stored, but still cached", file.uuid);
+        report.record(Finding::StaleCacheEntry);
+
+        if self.policy.repairs() {
+            match self.cache.write().await.delete(file.uuid).await {
+                Ok(()) => report.resolve(Action::Deleted),
+                Err(err) => tracing::error!("Deleting cache entry of {} failed: {err}", file.uuid),
+            }
+        }
+    }
+
+    /// Check that the contents of every file are either stored or queued for synchronization.
+    /// Returns the storage idents of all files.
+    async fn reconcile_files(
+        &self,
+        started: DateTime<Utc>,
+        stored: Option<&HashSet<String>>,
+        queued: &HashSet<Uuid>,
+        report: &mut Report,
+    ) -> Result<HashSet<String>> {
+        let mut idents = HashSet::new();
+        let mut cursor = None;
+
+        loop {
+            let files = self.database.get_files_after(cursor, BATCH_SIZE).await?;
+            let Some(last) = files.last() else {
+                break;
+            };
+            cursor = Some((last.createdAt, last.uuid));
+
+            for file in files {
+                let ident = file.storage_ident();
+                idents.insert(ident.clone());
+
+                let Some(stored) = stored else {
+                    continue;
+                };
+                // Files uploaded meanwhile may not be stored yet, expired files are handled by the expiration worker
+                if file.createdAt > started
+                    || file.expiresAt < started
+                    || queued.contains(&file.uuid)
+                {
+                    continue;
+                }
+                if stored.contains(&ident) {
+                    self.reconcile_cache_entry(&file, report).await;
+                    continue;
+                }
+
+                let unsynced =
+                    self.spool.exists(file.uuid).await || self.cache.read().await.exists(file.uuid);
+
+                if unsynced {
+                    tracing::warn!(
+                        "File {} is not stored and not queued for synchronization",
+                        file.uuid
+                    );
+                    report.record(Finding::UnsyncedFile);
+
+                    if self.policy.repairs() {
+                        match self.requeue(&file).await {
+                            Ok(()) => report.resolve(Action::Requeued),
+                            Err(err) => tracing::error!("Requeueing {} failed: {err}", file.uuid),
+                        }
+                    }
+                } else {
+                    tracing::warn!("Contents of file {} are missing", file.uuid);
+                    report.record(Finding::MissingContent);
+
+                    // The expiration worker deletes the file like any other expired file
+                    if self.policy == ReconciliationPolicy::Purge {
+                        let file = File {
+                            expiresAt: Utc::now(),
+                            ..file
+                        };
+                        match self.database.update_file_expiry(file).await {
+                            Ok(()) => report.resolve(Action::Expired),
+                            Err(err) => tracing::error!("Expiring file failed: {err}"),
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(idents)
+    }
+
+    /// Delete provider objects which are not referenced by any file or blob.
+    /// Objects not named like stored files never belong to hdrop and are left alone.
+    async fn reconcile_objects(
+        &self,
+        stored: HashSet<String>,
+        referenced: &HashSet<String>,
+        report: &mut Report,
+    ) {
+        let orphaned = stored
+            .difference(referenced)
+            .filter(|ident| File::is_storage_ident(ident));
+
+        for ident in orphaned {
+            tracing::warn!("Stored object {ident} does not belong to any file");
+            report.record(Finding::OrphanedObject);
+
+            if self.policy.repairs() && self.delete_orphans {
+                match self.provider.delete_file(ident.clone()).await {
+                    Ok(()) => report.resolve(Action::Deleted),
+                    Err(err) => tracing::error!("Deleting orphaned object {ident} failed: {err}"),
+                }
+            }
+        }
+    }
+
+    /// Delete spool files which belong to neither a file nor an upload.
+    async fn reconcile_spool(&self, report: &mut Report) -> Result<()> {
+        let spooled = self.spool.list_idle(SPOOL_GRACE_PERIOD).await?;
+        // Loaded after the spool, so files created meanwhile are known
+        let uploads: HashSet<Uuid> = self
+            .database
+            .get_upload_uuids()
+            .await?
+            .into_iter()
+            .collect();
+
+        for key in spooled {
+            if uploads.contains(&key) {
+                continue;
+            }
+            match self.database.get_file_by_uuid(key).await {
+                Ok(_) => continue,
+                Err(err) if err.is_not_found() => (),
+                Err(err) => return Err(err.into()),
+            }
+
+            tracing::warn!("Spool file {key} does not belong to any file or upload");
+            report.record(Finding::OrphanedSpoolFile);
+
+            if self.policy.repairs() {
+                match self.spool.delete(key).await {
+                    Ok(()) => report.resolve(Action::Deleted),
+                    Err(err) => tracing::error!("Deleting orphaned spool file {key} failed: {err}"),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn reconcile(&self) -> Result<()> {
+        let mut report = Report::default();
+        let started = Utc::now();
+
+        // Sync jobs are loaded before listing the provider, so finished jobs are found in the listing
+        let queued: HashSet<Uuid> = self
+            .database
+            .get_sync_job_uuids()
+            .await?
+            .into_iter()
+            .collect();
+
+        let stored = match self.provider.list_files().await {
+            Ok(idents) => Some(idents.into_iter().collect::<HashSet<_>>()),
+            Err(Error::ListingUnsupported) => {
+                tracing::debug!("Storage provider cannot list files, skipping storage checks");
+                None
+            }
+            Err(err) => return Err(err),
+        };
+
+        // Files are loaded after listing the provider, so every listed object of a new file is referenced
+        let mut referenced = self
+            .reconcile_files(started, stored.as_ref(), &queued, &mut report)
+            .await?;
+        referenced.extend(self.database.get_blob_hashes().await?);
+
+        if let Some(stored) = stored {
+            self.reconcile_objects(stored, &referenced, &mut report)
+                .await;
+        }
+
+        self.reconcile_spool(&mut report).await?;
+
+        tracing::info!(
+            "Reconciliation finished: {} orphaned objects, {} orphaned spool files, {} stale cache entries, {} unsynced files, {} missing contents, {} resolved ({:?})",
+            report.orphaned_objects,
+            report.orphaned_spool_files,
+            report.stale_cache_entries,
+            report.unsynced_files,
+            report.missing_contents,
+            report.resolved,
+            self.policy
+        );
+        Ok(())
+    }
+
+    #[instrument(skip(self), fields(policy = ?self.policy, delete_orphans = self.delete_orphans))]
+    pub async fn run(self) {
+        tracing::info!("Reconciliation worker started");
+
+        loop {
+            if let Err(err) = self.reconcile().await {
+                tracing::error!("Reconciliation failed: {err}");
+            }
+
+            match self.interval {
+                Some(interval) => tokio::time::sleep(interval).await,
+                None => return,
+            }
+        }
+    }
+}
@@ -0,0 +1,294 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use chrono::Utc;
+use hdrop_db::{Database, File, StorageMigration};
+use hdrop_shared::metrics::names;
+use tokio::{
+    task::JoinHandle,
+    time::{interval, Interval, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::instrument;
+use uuid::Uuid;
+
+use crate::{core::StorageProvider, error::Error, Result};
+
+/// Number of files loaded from the database at once.
+const BATCH_SIZE: i64 = 100;
+/// Time between two passes over files uploaded meanwhile and files to retry.
+const FOLLOW_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Copies all stored files from one storage provider to another.
+///
+/// Files are processed in creation order. The position of the last processed file is saved in the database,
+/// so an interrupted migration continues where it stopped. Files which fail, or are not stored on the source yet,
+/// are kept in a retry list. Once all files are processed, the migration keeps copying files uploaded meanwhile
+/// and retrying the list until it is stopped.
+pub struct StorageMigrator {
+    source_name: String,
+    target_name: String,
+    source: Arc<dyn StorageProvider + Sync + Send>,
+    target: Arc<dyn StorageProvider + Sync + Send>,
+    database: Arc<Database>,
+    rate_limit: Option<Interval>,
+    copied_bytes: u64,
+    /// Once cancelled, the migration stops after the current file.
+    stop: CancellationToken,
+}
+
+/// Handle of a storage migration running in the background.
+pub struct StorageMigrationHandle {
+    pub source: String,
+    pub target: String,
+    stop: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl StorageMigrationHandle {
+    pub fn is_running(&self) -> bool {
+        !self.task.is_finished()
+    }
+
+    /// Stop the migration after the current file. The progress is kept, so starting it again continues.
+    pub fn stop(&self) {
+        self.stop.cancel();
+    }
+}
+
+impl StorageMigrator {
+    /// Create a migration between two providers, named like in `STORAGE_PROVIDER`.
+    /// `files_per_second` limits the number of files copied per second.
+    pub fn new(
+        source_name: String,
+        source: Arc<dyn StorageProvider + Sync + Send>,
+        target_name: String,
+        target: Arc<dyn StorageProvider + Sync + Send>,
+        database: Arc<Database>,
+        files_per_second: Option<f64>,
+        stop: CancellationToken,
+    ) -> Result<Self> {
+        if source_name == target_name {
+            return Err(Error::InvalidArguments(
+                "Source and target provider must differ".to_string(),
+            ));
+        }
+
+        let rate_limit = files_per_second.filter(|rate| *rate > 0.0).map(|rate| {
+            let mut rate_limit = interval(Duration::from_secs_f64(1.0 / rate));
+            rate_limit.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            rate_limit
+        });
+
+        Ok(Self {
+            source_name,
+            target_name,
+            source,
+            target,
+            database,
+            rate_limit,
+            copied_bytes: 0,
+            stop,
+        })
+    }
+
+    /// Discard the saved progress, so the next run starts with the first file again.
+    pub async fn reset(&self) -> Result<()> {
+        self.database
+            .delete_storage_migration(self.source_name.clone(), self.target_name.clone())
+            .await?;
+        Ok(())
+    }
+
+    /// Run the migration in the background.
+    pub fn spawn(self) -> StorageMigrationHandle {
+        StorageMigrationHandle {
+            source: self.source_name.clone(),
+            target: self.target_name.clone(),
+            stop: self.stop.clone(),
+            task: tokio::spawn(async move {
+                if let Err(err) = self.run().await {
+                    tracing::error!("Storage migration failed: {err}");
+                }
+            }),
+        }
+    }
+
+    /// Copy a single file, verify the copy on the target provider and point the file to it.
+    /// Returns the number of bytes copied.
+    async fn migrate_file(&self, file: &File) -> Result<u64> {
+        let ident = file.storage_ident();
+
+        let content = self.source.read_file(ident.clone()).await?;
+        let size = content.size;
+        let data_url = self.target.store_file(ident.clone(), content).await?;
+
+        // Only count copies which are verifiably stored
+        if !self.target.file_exists(ident.clone()).await? {
+            return Err(Error::MigrationVerification(ident));
+        }
+
+        // The URL of the source provider is stale once the target provider serves the file
+        self.database
+            .update_data_url(file.uuid, data_url.as_ref())
+            .await?;
+
+        Ok(size)
+    }
+
+    /// Migrate a file, respecting the rate limit. Returns whether it succeeded.
+    async fn try_migrate(&mut self, file: &File) -> bool {
+        if let Some(rate_limit) = self.rate_limit.as_mut() {
+            rate_limit.tick().await;
+        }
+
+        match self.migrate_file(file).await {
+            Ok(size) => {
+                tracing::trace!("Migrated {}", file.uuid);
+                self.copied_bytes += size;
+                true
+            }
+            Err(err) => {
+                tracing::error!("Migrating {} failed, retrying later: {err}", file.uuid);
+                false
+            }
+        }
+    }
+
+    /// Files of the given ones which still wait in the spool, so they are not on the source provider yet.
+    async fn pending_sync(&self, uuids: Vec<Uuid>) -> Result<HashSet<Uuid>> {
+        Ok(self
+            .database
+            .get_sync_jobs_by_uuid(uuids)
+            .await?
+            .into_iter()
+            .map(|job| job.uuid)
+            .collect())
+    }
+
+    async fn save(&self, progress: &mut StorageMigration) -> Result<()> {
+        progress.updatedAt = Utc::now();
+        self.database
+            .save_storage_migration(progress.clone())
+            .await?;
+        self.update_metrics(progress);
+        Ok(())
+    }
+
+    fn update_metrics(&self, progress: &StorageMigration) {
+        metrics::gauge!(names::migration::FILES_MIGRATED, progress.migrated as f64);
+        metrics::gauge!(
+            names::migration::FILES_FAILED,
+            progress.retryUuids.len() as f64
+        );
+        metrics::gauge!(names::migration::COPIED_B, self.copied_bytes as f64);
+    }
+
+    /// Migrate all files after the cursor, until no files are left or the migration is stopped.
+    async fn migrate_new_files(&mut self, progress: &mut StorageMigration) -> Result<()> {
+        loop {
+            let cursor = progress.cursorCreatedAt.zip(progress.cursorUuid);
+            let files = self.database.get_files_after(cursor, BATCH_SIZE).await?;
+            if files.is_empty() {
+                return Ok(());
+            }
+
+            let pending = self
+                .pending_sync(files.iter().map(|file| file.uuid).collect())
+                .await?;
+
+            for file in files {
+                if self.stop.is_cancelled() {
+                    return Ok(());
+                }
+
+                if pending.contains(&file.uuid) {
+                    tracing::trace!("{} is not synchronized yet, retrying later", file.uuid);
+                    progress.retryUuids.push(file.uuid);
+                } else if self.try_migrate(&file).await {
+                    progress.migrated += 1;
+                } else {
+                    progress.retryUuids.push(file.uuid);
+                }
+
+                progress.cursorCreatedAt = Some(file.createdAt);
+                progress.cursorUuid = Some(file.uuid);
+                self.save(progress).await?;
+            }
+        }
+    }
+
+    /// Retry files which could not be migrated before. Files which fail again stay in the retry list.
+    async fn retry_files(&mut self, progress: &mut StorageMigration) -> Result<()> {
+        if progress.retryUuids.is_empty() {
+            return Ok(());
+        }
+
+        let pending = self.pending_sync(progress.retryUuids.clone()).await?;
+        let mut remaining = Vec::new();
+
+        for uuid in std::mem::take(&mut progress.retryUuids) {
+            if self.stop.is_cancelled() || pending.contains(&uuid) {
+                remaining.push(uuid);
+                continue;
+            }
+
+            match self.database.get_file_by_uuid(uuid).await {
+                Ok(file) if self.try_migrate(&file).await => progress.migrated += 1,
+                Ok(_) => remaining.push(uuid),
+                // Expired files do not need to be migrated anymore
+                Err(err) if err.is_not_found() => (),
+                Err(err) => {
+                    tracing::error!("Could not load {uuid} for migration: {err}");
+                    remaining.push(uuid);
+                }
+            }
+        }
+
+        progress.retryUuids = remaining;
+        self.save(progress).await
+    }
+
+    #[instrument(skip(self), fields(source = %self.source_name, target = %self.target_name))]
+    pub async fn run(mut self) -> Result<()> {
+        let mut progress = self
+            .database
+            .get_storage_migration(self.source_name.clone(), self.target_name.clone())
+            .await?
+            .unwrap_or_else(|| StorageMigration {
+                source: self.source_name.clone(),
+                target: self.target_name.clone(),
+                ..Default::default()
+            });
+
+        if progress.cursorUuid.is_some() {
+            tracing::info!(
+                "Resuming storage migration after {} migrated files",
+                progress.migrated
+            );
+        } else {
+            tracing::info!("Storage migration started");
+        }
+        self.update_metrics(&progress);
+
+        loop {
+            let total = self.database.get_file_rows().await?;
+            metrics::gauge!(names::migration::FILES_TOTAL, total as f64);
+
+            self.migrate_new_files(&mut progress).await?;
+            self.retry_files(&mut progress).await?;
+
+            // Files uploaded meanwhile are still written to the source provider
+            tokio::select! {
+                _ = tokio::time::sleep(FOLLOW_INTERVAL) => (),
+                _ = self.stop.cancelled() => break,
+            }
+        }
+
+        tracing::info!(
+            "Storage migration stopped: {} files migrated, {} files to retry",
+            progress.migrated,
+            progress.retryUuids.len()
+        );
+        Ok(())
+    }
+}
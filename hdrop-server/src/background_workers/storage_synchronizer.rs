@@ -1,90 +1,168 @@
 use std::{sync::Arc, time::Duration};
 
-use axum::body::Bytes;
+use async_trait::async_trait;
 use chrono::Utc;
-use hdrop_db::Database;
-use tokio::sync::{mpsc::Receiver, RwLock};
-use tracing::instrument;
+use hdrop_db::{Database, SyncJob};
+use hdrop_shared::metrics::{names, UpdateMetrics};
+use tokio::sync::{Notify, RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{instrument, Span};
 use uuid::Uuid;
 
-use crate::{core::StorageProvider, error::Result, server::CacheVariant};
+use crate::{
+    core::StorageProvider,
+    error::Result,
+    server::{CacheVariant, Spool},
+    telemetry,
+};
 
-pub struct ProviderSyncEntry {
-    pub provider: Arc<RwLock<Box<dyn StorageProvider + Sync + Send>>>,
-    pub database: Arc<Database>,
-    pub file_data: Bytes,
-    pub uuid: Uuid,
-    pub cache: Arc<RwLock<CacheVariant>>,
-}
+/// Time between two runs of the repair worker.
+const REPAIR_INTERVAL: Duration = Duration::from_secs(300);
+/// Maximum number of sync jobs claimed at once.
+const BATCH_SIZE: i64 = 8;
+/// Time a claimed sync job is reserved for this worker. Jobs of crashed workers are retried afterwards.
+const LEASE_MINUTES: i64 = 10;
+/// Time between two renewals of the lease of a running sync job, so long transfers keep their job.
+const LEASE_RENEWAL_INTERVAL: Duration = Duration::from_secs(120);
+/// Time between two checks for due retries while no new files arrive.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
 
+/// Synchronizes uploaded files from the spool to the storage provider.
+///
+/// Files are queued as sync jobs in the database, so pending synchronizations survive restarts.
+/// Failed jobs are retried with exponential backoff until the file expires.
 pub struct StorageSynchronizer {
-    pub rx: Receiver<ProviderSyncEntry>,
+    provider: Arc<dyn StorageProvider + Sync + Send>,
+    database: Arc<Database>,
+    cache: Arc<RwLock<CacheVariant>>,
+    spool: Arc<Spool>,
+    /// Held for reading while a deduplicated blob is stored, so the expiration worker does not delete it meanwhile.
+    blob_lock: Arc<RwLock<()>>,
+    /// Wakes the synchronizer when a new job is queued.
+    notify: Arc<Notify>,
+    /// Once cancelled, the synchronizer finishes all due jobs and stops.
+    drain: CancellationToken,
 }
 
 impl StorageSynchronizer {
-    pub fn new(rx: Receiver<ProviderSyncEntry>) -> Self {
-        Self { rx }
+    pub fn new(
+        provider: Arc<dyn StorageProvider + Sync + Send>,
+        database: Arc<Database>,
+        cache: Arc<RwLock<CacheVariant>>,
+        spool: Arc<Spool>,
+        blob_lock: Arc<RwLock<()>>,
+        notify: Arc<Notify>,
+        drain: CancellationToken,
+    ) -> Self {
+        Self {
+            provider,
+            database,
+            cache,
+            spool,
+            blob_lock,
+            notify,
+            drain,
+        }
     }
 
-    pub async fn synchronize(provider_sync_entry: ProviderSyncEntry) {
-        // Check if file got successfully stored on StorageProvider
-        match Self::save_to_provider(&provider_sync_entry).await {
-            Ok(url) => {
-                tracing::trace!("File stored to {url:?}");
-                Self::update_db_and_cache(
-                    url,
-                    provider_sync_entry.database,
-                    provider_sync_entry.uuid,
-                    provider_sync_entry.cache,
-                )
-                .await;
-                return;
-            }
-            Err(_err) => {
-                tracing::error!("File could not be stored on StorageProvider");
-            }
+    /// Store the file on the storage provider and clear it from the cache and the spool.
+    async fn synchronize(&self, job: &SyncJob) -> Result<()> {
+        // Expired files get deleted by the expiration worker, together with their job
+        let file = self.database.get_file_by_uuid(job.uuid).await?;
+        if Utc::now() > file.expiresAt {
+            tracing::trace!("File already expired, skipping storage synchronization");
+            return Ok(self.database.complete_sync_job(job.uuid).await?);
+        }
+
+        // Stream file from the spool to the StorageProvider
+        let content = self.spool.open(job.uuid, None).await?;
+        let _blob_lock = match file.blobHash {
+            Some(_) => Some(self.blob_lock.read().await),
+            None => None,
         };
+        let data_url = self.provider.store_file(job.ident.clone(), content).await?;
+        tracing::trace!("File stored to {data_url:?}");
+
+        // Database update DataUrl here
+        self.database
+            .update_data_url(job.uuid, data_url.as_ref())
+            .await?;
+        tracing::trace!("File dataUrl successfully updated in database");
+        self.database.complete_sync_job(job.uuid).await?;
+
+        // Time from the upload until the contents are safely stored
+        let latency = (Utc::now() - file.createdAt).num_milliseconds() as f64 / 1000.0;
+        metrics::histogram!(names::sync::LATENCY_SECONDS, latency);
 
-        // Start background task to retry synchronization
-        tokio::spawn(Self::synchronization_retry_worker(provider_sync_entry));
+        Self::clear_cache(self.cache.clone(), job.uuid).await;
+        Self::clear_spool(self.spool.clone(), job.uuid).await;
+        Ok(())
     }
 
-    pub async fn save_to_provider(
-        provider_sync_entry: &ProviderSyncEntry,
-    ) -> Result<Option<String>> {
-        // Store file on StorageProvider
-        provider_sync_entry
-            .provider
-            .write()
+    /// Run a sync job, scheduling a retry if it fails.
+    /// Every attempt gets its own span, linked to the request which queued the job.
+    #[instrument(parent = None, skip_all, fields(uuid = %job.uuid, attempt = job.attempts + 1))]
+    async fn process(&self, job: SyncJob) {
+        if let Some(ref trace_context) = job.traceContext {
+            telemetry::link(&Span::current(), trace_context);
+        }
+
+        let synchronize = self.synchronize(&job);
+        tokio::pin!(synchronize);
+        let mut renewal = tokio::time::interval_at(
+            tokio::time::Instant::now() + LEASE_RENEWAL_INTERVAL,
+            LEASE_RENEWAL_INTERVAL,
+        );
+
+        let result = loop {
+            tokio::select! {
+                result = &mut synchronize => break result,
+                _ = renewal.tick() => self.renew_lease(job.uuid).await,
+            }
+        };
+        let Err(err) = result else {
+            return;
+        };
+
+        tracing::error!(
+            "[Attempt {}] File {} could not be stored on StorageProvider: {err}",
+            job.attempts + 1,
+            job.uuid
+        );
+        metrics::increment_counter!(names::sync::RETRIES_TOTAL);
+        let run_at = Utc::now() + Self::backoff(job.attempts as u32);
+        if let Err(err) = self
+            .database
+            .retry_sync_job(job.uuid, run_at, err.to_string())
             .await
-            .store_file(
-                provider_sync_entry.uuid.to_string(),
-                &provider_sync_entry.file_data,
-            )
+        {
+            // The job is retried anyway once its lease runs out
+            tracing::error!("Could not schedule retry of sync job {}: {err}", job.uuid);
+        }
+    }
+
+    async fn renew_lease(&self, uuid: Uuid) {
+        // Failing renewals only risk a concurrent attempt once the lease runs out
+        if let Err(err) = self
+            .database
+            .renew_sync_job_lease(uuid, chrono::Duration::minutes(LEASE_MINUTES))
             .await
+        {
+            tracing::warn!("Could not renew lease of sync job {uuid}: {err}");
+        }
     }
 
-    pub async fn update_db_and_cache(
-        data_url: Option<String>,
-        database: Arc<Database>,
-        uuid: Uuid,
-        cache: Arc<RwLock<CacheVariant>>,
-    ) {
-        // Database update DataUrl here
-        if let Err(err) = database.update_data_url(uuid, data_url.as_ref()).await {
-            tracing::error!("Database data url update failed: {err}");
-            tokio::spawn(Self::database_retry_worker(data_url, database, uuid));
-        } else {
-            tracing::trace!("File dataUrl successfully updated in database");
-            //clear_cache()
-            Self::clear_cache(cache, uuid).await;
+    pub async fn clear_spool(spool: Arc<Spool>, file_uuid: Uuid) {
+        // Leftovers are harmless, they get removed together with the expired file
+        if let Err(err) = spool.delete(file_uuid).await {
+            tracing::error!("Could not delete file from spool: {err}");
         }
     }
 
     pub async fn clear_cache(cache: Arc<RwLock<CacheVariant>>, file_uuid: Uuid) {
-        if let Err(err) = Self::check_and_delete_cache_entry(cache.clone(), file_uuid).await {
+        // Leftovers only take up cache capacity, they get removed by the reconciliation worker
+        if let Err(err) = Self::check_and_delete_cache_entry(cache, file_uuid).await {
             tracing::error!("Could not delete file from cache: {err}");
-            tokio::spawn(Self::cache_retry_worker(cache.clone(), file_uuid));
         }
     }
 
@@ -106,102 +184,85 @@ impl StorageSynchronizer {
     }
 
     #[instrument(skip(self))]
-    pub async fn run(mut self) -> Result<()> {
+    pub async fn run(self) -> Result<()> {
         tracing::info!("Storage synchronizer started");
 
-        // Synchronize every received file to storage
-        while let Some(provider_sync_entry) = self.rx.recv().await {
-            Self::synchronize(provider_sync_entry).await;
+        // Jobs left over from the last session are picked up like new ones
+        if let Ok((queued, _)) = self.database.get_sync_queue_stats().await {
+            if queued > 0 {
+                tracing::info!("Resuming {queued} queued storage synchronizations");
+            }
         }
 
-        tracing::warn!("Storage synchronizer stopped");
-        Ok(())
-    }
+        loop {
+            self.update_metrics().await;
 
-    /*
-        Retry workers that spawn in error scenarios
-    */
-
-    pub async fn synchronization_retry_worker(retry_sync_entry: ProviderSyncEntry) {
-        tracing::warn!("Storage retry worker started for one file");
-        for i in 0..=6u32 {
-            tracing::info!(
-                "[Attempt {i}] Retrying to sync {} (attempt {i})",
-                retry_sync_entry.uuid
-            );
-            // Check if file is already expired
-            match retry_sync_entry
+            let jobs = self
                 .database
-                .get_file_by_uuid(retry_sync_entry.uuid)
+                .claim_sync_jobs(BATCH_SIZE, chrono::Duration::minutes(LEASE_MINUTES))
                 .await
-            {
-                Ok(file) => {
-                    if Utc::now() > file.expiresAt {
-                        tracing::trace!(
-                            "File already expired, stopping storage synchronization retry worker"
-                        );
-                        return;
-                    }
-                }
-
-                Err(err) => tracing::warn!("{err}"),
-            }
+                .unwrap_or_else(|err| {
+                    tracing::error!("Could not claim sync jobs: {err}");
+                    Vec::new()
+                });
 
-            match Self::save_to_provider(&retry_sync_entry).await {
-                Ok(url) => {
-                    tracing::trace!("File stored to {url:?}");
-                    Self::update_db_and_cache(
-                        url,
-                        retry_sync_entry.database,
-                        retry_sync_entry.uuid,
-                        retry_sync_entry.cache,
-                    )
-                    .await;
-                    return;
+            if jobs.is_empty() {
+                // Jobs scheduled for a later retry are resumed on the next start
+                if self.drain.is_cancelled() {
+                    tracing::info!("Storage synchronizer drained");
+                    return Ok(());
                 }
 
-                Err(_err) => {
-                    tracing::error!(
-                        "Retry worker failed, file could not be stored on StorageProvider"
-                    );
-                    Self::exponential_backoff(i).await;
+                // Wait for a new job, due retries are found by polling
+                tokio::select! {
+                    _ = self.notify.notified() => (),
+                    _ = tokio::time::sleep(POLL_INTERVAL) => (),
+                    _ = self.drain.cancelled() => (),
                 }
-            };
-        }
-    }
+                continue;
+            }
 
-    pub async fn database_retry_worker(
-        data_url: Option<String>,
-        database: Arc<Database>,
-        uuid: Uuid,
-    ) {
-        for i in 0..=6u32 {
-            if let Err(err) = database.update_data_url(uuid, data_url.as_ref()).await {
-                tracing::error!("Retry worker failed, database dataUrl update failed: {err}");
-                Self::exponential_backoff(i).await;
-            } else {
-                tracing::trace!("File dataUrl successfully updated in database");
-                return;
+            // Synchronize every queued file to storage
+            for job in jobs {
+                self.process(job).await;
             }
         }
     }
 
-    pub async fn cache_retry_worker(cache: Arc<RwLock<CacheVariant>>, file_uuid: Uuid) {
-        for i in 0..=6u32 {
-            if let Err(err) = Self::check_and_delete_cache_entry(cache.clone(), file_uuid).await {
-                tracing::error!("Retry worker failed, could not delete file from cache: {err}");
-                Self::exponential_backoff(i).await;
-            } else {
-                tracing::trace!("Cache retry worker successful. Closing Thread.");
-                return;
+    /// Periodically restore missing copies of stored files, e.g. on replicas which were unavailable during the upload.
+    #[instrument(skip(provider))]
+    pub async fn repair_worker(provider: Arc<dyn StorageProvider + Sync + Send>) {
+        loop {
+            tokio::time::sleep(REPAIR_INTERVAL).await;
+
+            match provider.repair().await {
+                Ok(0) => (),
+                Ok(count) => tracing::info!("Restored {count} missing file copies"),
+                Err(err) => tracing::error!("Storage repair failed: {err}"),
             }
         }
     }
 
-    // Simple exponential backoff
-    pub async fn exponential_backoff(exponent: u32) {
-        let exponential_base: u64 = 2;
-        let timer = exponential_base.pow(exponent).min(60) * 60;
-        tokio::time::sleep(Duration::from_secs(timer)).await;
+    /// Delay before the next attempt after the given number of failed attempts, from one minute up to an hour.
+    fn backoff(exponent: u32) -> chrono::Duration {
+        let exponential_base: i64 = 2;
+        chrono::Duration::minutes(exponential_base.pow(exponent.min(6)).min(60))
+    }
+}
+
+#[async_trait]
+impl UpdateMetrics for StorageSynchronizer {
+    async fn update_metrics(&self) {
+        match self.database.get_sync_queue_stats().await {
+            Ok((queued, oldest)) => {
+                let age = oldest
+                    .map(|created_at| (Utc::now() - created_at).num_milliseconds() as f64 / 1000.0)
+                    .unwrap_or(0.0);
+
+                metrics::gauge!(names::sync::QUEUE_DEPTH, queued as f64);
+                metrics::gauge!(names::sync::OLDEST_JOB_AGE_SECONDS, age);
+            }
+            Err(err) => tracing::warn!("Could not determine sync queue metrics: {err}"),
+        }
     }
 }
@@ -1,11 +1,20 @@
+mod expiry_policy;
 mod metrics;
 mod providers;
+mod range;
 
 pub use self::{
+    expiry_policy::{ExpiryPolicy, ExpiryTier},
     metrics::monitoring,
     providers::{
+        composite_provider::{CompositeProvider, ReplicationPolicy},
+        create_provider,
         local_provider::LocalProvider,
-        provider::{Fetchtype, StorageProvider},
+        provider::{ByteStream, Fetchtype, FileStream, StorageProvider},
+        provider_kind,
+        provider_names_from_env,
         s3_provider::S3Provider,
+        storage_dir_from_env,
     },
+    range::{ByteRange, ContentRange},
 };
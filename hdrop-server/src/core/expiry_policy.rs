@@ -0,0 +1,136 @@
+use hdrop_shared::{
+    env,
+    responses::{ExpiryPolicyData, ExpiryTierData},
+};
+
+use crate::{error::Error, utils::mb_to_bytes, Result};
+
+/// Expiry in seconds of files uploaded without a requested expiry.
+const DEFAULT_EXPIRY: i64 = 86400;
+const DEFAULT_MIN_EXPIRY: i64 = 60;
+const DEFAULT_MAX_EXPIRY: i64 = 86400;
+
+/// Lower maximum expiry for files larger than a given size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExpiryTier {
+    /// Size in bytes above which the tier applies.
+    pub min_file_size: u64,
+    /// Maximum expiry in seconds.
+    pub max_expiry: i64,
+}
+
+/// Expiries a file may be given, configured via the `EXPIRY_*` environment variables.
+///
+/// `EXPIRY_TIERS` optionally lowers the maximum for large files, e.g. `100:3600,500:600`
+/// limits files above 100 MB to one hour and files above 500 MB to ten minutes.
+#[derive(Debug, Clone)]
+pub struct ExpiryPolicy {
+    default: i64,
+    min: i64,
+    max: i64,
+    tiers: Vec<ExpiryTier>,
+}
+
+impl ExpiryPolicy {
+    pub fn new(default: i64, min: i64, max: i64, mut tiers: Vec<ExpiryTier>) -> Result<Self> {
+        if min < 0 || min > max {
+            return Err(Error::InvalidExpiryPolicy(format!(
+                "Minimum expiry {min} must be between 0 and the maximum expiry {max}"
+            )));
+        }
+        if !(min..=max).contains(&default) {
+            return Err(Error::InvalidExpiryPolicy(format!(
+                "Default expiry {default} must be between {min} and {max}"
+            )));
+        }
+        if let Some(tier) = tiers.iter().find(|tier| tier.max_expiry < min) {
+            return Err(Error::InvalidExpiryPolicy(format!(
+                "Maximum expiry {} of the tier above {} bytes is below the minimum expiry {min}",
+                tier.max_expiry, tier.min_file_size
+            )));
+        }
+
+        tiers.sort_by_key(|tier| tier.min_file_size);
+
+        Ok(Self {
+            default,
+            min,
+            max,
+            tiers,
+        })
+    }
+
+    pub fn try_from_env() -> Result<Self> {
+        let min = env::expiry_min().unwrap_or(DEFAULT_MIN_EXPIRY);
+        let max = env::expiry_max().unwrap_or(DEFAULT_MAX_EXPIRY);
+        let default = env::expiry_default().unwrap_or(DEFAULT_EXPIRY.min(max));
+        let tiers = match env::expiry_tiers() {
+            Ok(tiers) => Self::parse_tiers(&tiers)?,
+            Err(_) => Vec::new(),
+        };
+
+        Self::new(default, min, max, tiers)
+    }
+
+    /// Parse a comma-separated list of tiers of the form `SIZE_MB:MAX_EXPIRY`.
+    fn parse_tiers(tiers: &str) -> Result<Vec<ExpiryTier>> {
+        tiers
+            .split(',')
+            .map(str::trim)
+            .filter(|tier| !tier.is_empty())
+            .map(|tier| {
+                let (size, max_expiry) = tier
+                    .split_once(':')
+                    .and_then(|(size, max_expiry)| {
+                        Some((size.trim().parse().ok()?, max_expiry.trim().parse().ok()?))
+                    })
+                    .ok_or_else(|| {
+                        Error::InvalidExpiryPolicy(format!("Invalid expiry tier '{tier}'"))
+                    })?;
+
+                Ok(ExpiryTier {
+                    min_file_size: mb_to_bytes(size) as u64,
+                    max_expiry,
+                })
+            })
+            .collect()
+    }
+
+    /// Maximum expiry in seconds of a file of the given size.
+    pub fn max_expiry(&self, file_size: u64) -> i64 {
+        self.tiers
+            .iter()
+            .filter(|tier| file_size > tier.min_file_size)
+            .map(|tier| tier.max_expiry)
+            .fold(self.max, i64::min)
+    }
+
+    /// Validate the requested expiry of a file of the given size.
+    /// Without a request, the default expiry applies, lowered to the maximum of the file size if necessary.
+    pub fn expiry(&self, requested: Option<i64>, file_size: u64) -> Result<chrono::Duration> {
+        let max = self.max_expiry(file_size);
+        let expiry = match requested {
+            Some(expiry) if (self.min..=max).contains(&expiry) => expiry,
+            Some(_) => return Err(Error::InvalidExpiry),
+            None => self.default.min(max),
+        };
+
+        Ok(chrono::Duration::seconds(expiry))
+    }
+
+    pub fn to_data(&self) -> ExpiryPolicyData {
+        ExpiryPolicyData {
+            default_expiry: self.default,
+            min_expiry: self.min,
+            max_expiry: self.max,
+            tiers: self
+                .tiers
+                .iter()
+                .map(|tier| ExpiryTierData {
+                    min_file_size: tier.min_file_size,
+                    max_expiry: tier.max_expiry,
+                })
+                .collect(),
+        }
+    }
+}
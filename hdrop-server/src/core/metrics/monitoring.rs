@@ -1,4 +1,6 @@
-use sysinfo::{CpuExt, System, SystemExt};
+use std::path::Path;
+
+use sysinfo::{CpuExt, DiskExt, NetworkExt, ProcessExt, System, SystemExt};
 
 /// Agnostic status struct for any limited resource.
 pub struct Status {
@@ -15,7 +17,6 @@ impl Status {
     }
 
     /// Get the total capacity.
-    #[allow(dead_code)]
     pub fn total(&self) -> usize {
         self.total_amount
     }
@@ -31,6 +32,20 @@ impl Status {
     }
 }
 
+/// Traffic of a network interface since boot.
+pub struct NetworkStatus {
+    pub interface: String,
+    pub received: u64,
+    pub transmitted: u64,
+}
+
+/// Resource usage of the server process.
+pub struct ProcessStatus {
+    pub resident_memory: u64,
+    /// Number of open file descriptors, only available on Linux.
+    pub open_fds: Option<usize>,
+}
+
 /// Struct to persist system monitoring.
 pub struct SystemMetrics {
     sys: System,
@@ -63,4 +78,53 @@ impl SystemMetrics {
 
         cpus
     }
+
+    /// Get the traffic of all network interfaces
+    pub fn network_status(&mut self) -> Vec<NetworkStatus> {
+        // Interfaces may come and go, e.g. with containers
+        self.sys.refresh_networks_list();
+
+        self.sys
+            .networks()
+            .iter()
+            .map(|(interface, network)| NetworkStatus {
+                interface: interface.clone(),
+                received: network.total_received(),
+                transmitted: network.total_transmitted(),
+            })
+            .collect()
+    }
+
+    /// Get the usage of the disk holding the given path, `None` if the path does not exist
+    pub fn disk_status(&mut self, path: &Path) -> Option<Status> {
+        let path = path.canonicalize().ok()?;
+        self.sys.refresh_disks_list();
+
+        // The disk is mounted at the longest prefix of the path
+        self.sys
+            .disks()
+            .iter()
+            .filter(|disk| path.starts_with(disk.mount_point()))
+            .max_by_key(|disk| disk.mount_point().as_os_str().len())
+            .map(|disk| {
+                Status::new(
+                    disk.total_space() as usize,
+                    disk.total_space().saturating_sub(disk.available_space()) as usize,
+                )
+            })
+    }
+
+    /// Get the resource usage of the server process
+    pub fn process_status(&mut self) -> Option<ProcessStatus> {
+        let pid = sysinfo::get_current_pid().ok()?;
+        self.sys.refresh_process(pid);
+        let process = self.sys.process(pid)?;
+
+        Some(ProcessStatus {
+            resident_memory: process.memory(),
+            open_fds: std::fs::read_dir("/proc/self/fd")
+                .ok()
+                .map(|fds| fds.count()),
+        })
+    }
 }
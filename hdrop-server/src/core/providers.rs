@@ -1,3 +1,67 @@
+pub mod composite_provider;
+pub mod filesystem_provider;
 pub mod local_provider;
 pub mod provider;
 pub mod s3_provider;
+
+use std::{path::PathBuf, sync::Arc};
+
+use hdrop_db::Database;
+use hdrop_shared::env;
+
+use self::{
+    filesystem_provider::FilesystemProvider,
+    local_provider::LocalProvider,
+    provider::StorageProvider,
+    s3_provider::S3Provider,
+};
+use crate::{error::Error, Result};
+
+/// Names of the providers configured in `STORAGE_PROVIDER`, the primary provider first.
+pub fn provider_names_from_env() -> Result<Vec<String>> {
+    let names = env::storage_provider()
+        .map_err(|_| Error::NoProvider)?
+        .split(',')
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    Ok(names)
+}
+
+/// Kind of a named provider, e.g. `s3` for `s3@backup`.
+pub fn provider_kind(name: &str) -> &str {
+    name.split_once('@').map_or(name, |(kind, _)| kind)
+}
+
+/// Local directory of a named provider, `None` for providers which do not store files locally.
+pub fn storage_dir_from_env(name: &str) -> Option<PathBuf> {
+    let instance = name.split_once('@').map(|(_, instance)| instance);
+
+    match provider_kind(name) {
+        "filesystem" => Some(FilesystemProvider::storage_dir_from_env(instance)),
+        "local" => Some(LocalProvider::storage_dir_from_env(instance)),
+        _ => None,
+    }
+}
+
+/// Create a single storage provider by name, e.g. `filesystem`, `local` or `s3`.
+/// Names of the form `kind@instance` create an additional instance with prefixed settings, e.g. `s3@backup`.
+pub async fn create_provider(
+    name: &str,
+    database: Arc<Database>,
+) -> Result<Box<dyn StorageProvider + Sync + Send>> {
+    let (kind, instance) = match name.split_once('@') {
+        Some((kind, instance)) => (kind, Some(instance)),
+        None => (name, None),
+    };
+
+    match kind {
+        "s3" => Ok(Box::new(S3Provider::try_from_env(
+            name, instance, database,
+        )?)),
+        "filesystem" => Ok(Box::new(FilesystemProvider::try_from_env(instance).await?)),
+        "local" => Ok(Box::new(LocalProvider::try_from_env(instance).await?)),
+        _ => Err(Error::InvalidProvider(name.to_string())),
+    }
+}
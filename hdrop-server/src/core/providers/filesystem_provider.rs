@@ -0,0 +1,424 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use hdrop_shared::{
+    env,
+    metrics::{names, UpdateMetrics},
+};
+use tokio::{fs, io::AsyncWriteExt, sync::Mutex};
+use uuid::Uuid;
+
+use super::provider::{Fetchtype, FileStream, StorageProvider};
+use crate::{core::ByteRange, error::Error, utils::mb_to_bytes, Result};
+
+/// Directory for files which are still being written.
+const TEMP_DIR: &str = "tmp";
+/// File holding the number of bytes stored, so it does not have to be determined on startup.
+const USAGE_FILE: &str = "usage";
+/// Number of characters of the ident used per directory level.
+const SHARD_WIDTH: usize = 2;
+/// Number of directory levels.
+const SHARD_DEPTH: usize = 2;
+
+/// StorageProvider storing files in a directory tree sharded by the ident prefix,
+/// e.g. `ab/cd/abcdef01-...`, which keeps directories small no matter how many files are stored.
+///
+/// Files are written to a temporary file first and atomically moved into place once they are synced to disk.
+/// The used storage is persisted next to the files, so starting the provider does not scan the directory tree.
+#[derive(Debug)]
+pub struct FilesystemProvider {
+    storage_path: PathBuf,
+    storage_limit: Option<u64>,
+    used_storage: AtomicU64,
+    /// Serializes writes of the usage file, so an outdated value never replaces a newer one.
+    usage_file: Mutex<()>,
+}
+
+impl FilesystemProvider {
+    /// Create the provider from the `FILESYSTEM_*` environment variables.
+    /// Named instances read prefixed variables, see [env::instance_var].
+    pub async fn try_from_env(instance: Option<&str>) -> Result<Self> {
+        let storage_path = Self::storage_dir_from_env(instance);
+        let storage_limit = env::instance_var(instance, "FILESYSTEM_STORAGE_LIMIT_MB")
+            .ok()
+            .map(|limit| mb_to_bytes(limit) as u64);
+
+        // Leftovers of interrupted writes are never completed, so they can be dropped
+        let temp_path = storage_path.join(TEMP_DIR);
+        if fs::try_exists(&temp_path).await? {
+            fs::remove_dir_all(&temp_path).await?;
+        }
+        fs::create_dir_all(&temp_path).await?;
+
+        let used_storage = Self::read_usage(&storage_path).await?;
+
+        Ok(Self {
+            storage_path,
+            storage_limit,
+            used_storage: AtomicU64::new(used_storage),
+            usage_file: Mutex::new(()),
+        })
+    }
+
+    /// Directory holding the stored files.
+    pub fn storage_dir_from_env(instance: Option<&str>) -> PathBuf {
+        env::instance_var(instance, "FILESYSTEM_STORAGE_DIR")
+            .unwrap_or_else(|_| PathBuf::from("storage"))
+    }
+
+    async fn read_usage(storage_path: &Path) -> Result<u64> {
+        match fs::read_to_string(storage_path.join(USAGE_FILE)).await {
+            Ok(usage) => Ok(usage.trim().parse().unwrap_or_else(|_| {
+                tracing::warn!("Invalid usage file in storage directory, starting from zero");
+                0
+            })),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+
+    /// Persist the used storage, replacing the usage file atomically.
+    async fn write_usage(&self) -> Result<()> {
+        let _usage_file = self.usage_file.lock().await;
+        let temp_path = self.temp_path(USAGE_FILE);
+        fs::write(&temp_path, self.used_storage().to_string()).await?;
+        fs::rename(&temp_path, self.storage_path.join(USAGE_FILE)).await?;
+        Ok(())
+    }
+
+    /// Path of a stored file, e.g. `<storage>/ab/cd/abcdef01-...`.
+    fn path(&self, ident: &str) -> PathBuf {
+        let mut path = self.storage_path.clone();
+        for level in 0..SHARD_DEPTH {
+            let shard = ident
+                .get(level * SHARD_WIDTH..(level + 1) * SHARD_WIDTH)
+                .unwrap_or("_");
+            path.push(shard);
+        }
+        path.join(ident)
+    }
+
+    /// Unique path of a file which is being written.
+    fn temp_path(&self, ident: &str) -> PathBuf {
+        self.storage_path
+            .join(TEMP_DIR)
+            .join(format!("{ident}.{}", Uuid::new_v4()))
+    }
+
+    fn used_storage(&self) -> u64 {
+        self.used_storage.load(Ordering::Relaxed)
+    }
+
+    /// Subtract the size of a removed or replaced file from the used storage.
+    fn release_storage(&self, size: u64) {
+        let _ = self
+            .used_storage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+
+    fn exceeds_limit(&self, additional_bytes: u64) -> bool {
+        self.storage_limit
+            .is_some_and(|limit| self.used_storage() + additional_bytes > limit)
+    }
+
+    /// Stream the content into a temporary file and sync it to disk. Returns the number of bytes written.
+    async fn write_temp(&self, temp_path: &Path, mut content: FileStream) -> Result<u64> {
+        let mut file = fs::File::create(temp_path).await?;
+        let mut written = 0;
+
+        while let Some(data) = content.stream.next().await {
+            let data = data?;
+            written += data.len() as u64;
+            if self.exceeds_limit(written) {
+                return Err(Error::StorageLimitExceeded);
+            }
+            file.write_all(&data).await?;
+        }
+
+        file.flush().await?;
+        file.sync_all().await?;
+
+        Ok(written)
+    }
+
+    /// Sync a directory, which makes renames within it durable.
+    async fn sync_dir(path: &Path) -> Result<()> {
+        fs::File::open(path).await?.sync_all().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StorageProvider for FilesystemProvider {
+    async fn store_file(&self, ident: String, content: FileStream) -> Result<Option<String>> {
+        if self.exceeds_limit(content.size) {
+            return Err(Error::StorageLimitExceeded);
+        }
+
+        let temp_path = self.temp_path(&ident);
+        let written = match self.write_temp(&temp_path, content).await {
+            Ok(written) => written,
+            Err(err) => {
+                let _ = fs::remove_file(&temp_path).await;
+                return Err(err);
+            }
+        };
+
+        let path = self.path(&ident);
+        let shard_path = path
+            .parent()
+            .expect("Stored files are always within a shard");
+        fs::create_dir_all(shard_path).await?;
+
+        // Replace an existing file with the same ident
+        if let Ok(metadata) = fs::metadata(&path).await {
+            self.release_storage(metadata.len());
+        }
+        fs::rename(&temp_path, &path).await?;
+        Self::sync_dir(shard_path).await?;
+
+        self.used_storage.fetch_add(written, Ordering::Relaxed);
+        self.write_usage().await?;
+
+        // Action-based update of metrics due to write operation
+        self.update_metrics().await;
+        Ok(None)
+    }
+
+    async fn delete_file(&self, ident: String) -> Result<()> {
+        let path = self.path(&ident);
+        let size = fs::metadata(&path).await?.len();
+        fs::remove_file(&path).await?;
+
+        self.release_storage(size);
+        self.write_usage().await?;
+
+        // Action-based update of metrics due to write operation
+        self.update_metrics().await;
+        Ok(())
+    }
+
+    async fn get_file(&self, ident: String) -> Result<Fetchtype> {
+        let file = fs::File::open(self.path(&ident)).await?;
+        Ok(Fetchtype::FileStream(
+            FileStream::from_file(file, None).await?,
+        ))
+    }
+
+    async fn get_file_range(&self, ident: String, range: ByteRange) -> Result<Fetchtype> {
+        let file = fs::File::open(self.path(&ident)).await?;
+        Ok(Fetchtype::FileStream(
+            FileStream::from_file(file, Some(range)).await?,
+        ))
+    }
+
+    async fn file_exists(&self, ident: String) -> Result<bool> {
+        Ok(fs::try_exists(self.path(&ident)).await?)
+    }
+
+    async fn health_check(&self) -> Result<()> {
+        if self
+            .storage_limit
+            .is_some_and(|limit| self.used_storage() >= limit)
+        {
+            return Err(Error::StorageLimitExceeded);
+        }
+
+        // Fails if the storage directory is missing or inaccessible
+        fs::read_dir(&self.storage_path).await?;
+        Ok(())
+    }
+
+    async fn list_files(&self) -> Result<Vec<String>> {
+        let mut idents = Vec::new();
+        let mut dirs = vec![(self.storage_path.clone(), 0)];
+
+        // Stored files only live at the bottom of the shard tree
+        while let Some((dir, level)) = dirs.pop() {
+            let mut entries = fs::read_dir(&dir).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let is_dir = entry.file_type().await?.is_dir();
+
+                if level == 0 && (name == TEMP_DIR || name == USAGE_FILE) {
+                    continue;
+                }
+
+                match (level < SHARD_DEPTH, is_dir) {
+                    (true, true) => dirs.push((entry.path(), level + 1)),
+                    (false, false) => idents.push(name),
+                    _ => {
+                        tracing::warn!("Unexpected entry in storage directory: {:?}", entry.path())
+                    }
+                }
+            }
+        }
+
+        Ok(idents)
+    }
+}
+
+#[async_trait]
+impl UpdateMetrics for FilesystemProvider {
+    async fn update_metrics(&self) {
+        // Update storage gauge
+        metrics::gauge!(names::storage::USED_STORAGE_B, self.used_storage() as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Bytes;
+    use futures::{stream, TryStreamExt};
+
+    use super::*;
+
+    const IDENT: &str = "abcdef01-2345-6789-abcd-ef0123456789";
+
+    /// Provider with its own instance name, so its settings do not interfere with other tests.
+    async fn provider(dir: &Path, limit_mb: Option<usize>) -> FilesystemProvider {
+        let instance = format!("fs_{}", dir.file_name().unwrap().to_string_lossy());
+        let prefix = instance.to_uppercase();
+        std::env::set_var(format!("{prefix}_FILESYSTEM_STORAGE_DIR"), dir);
+        if let Some(limit_mb) = limit_mb {
+            std::env::set_var(
+                format!("{prefix}_FILESYSTEM_STORAGE_LIMIT_MB"),
+                limit_mb.to_string(),
+            );
+        }
+
+        FilesystemProvider::try_from_env(Some(&instance))
+            .await
+            .unwrap()
+    }
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(Uuid::new_v4().simple().to_string())
+    }
+
+    fn content(data: &'static [u8]) -> FileStream {
+        FileStream::from_bytes(Bytes::from_static(data), None).unwrap()
+    }
+
+    async fn read(provider: &FilesystemProvider, ident: &str) -> Vec<u8> {
+        let content = provider.read_file(ident.to_string()).await.unwrap();
+        content
+            .stream
+            .try_fold(Vec::new(), |mut data, chunk| async move {
+                data.extend_from_slice(&chunk);
+                Ok(data)
+            })
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn files_are_stored_in_shards_of_their_ident() {
+        let dir = temp_dir();
+        let provider = provider(&dir, None).await;
+
+        provider
+            .store_file(IDENT.to_string(), content(b"contents"))
+            .await
+            .unwrap();
+
+        assert!(dir.join("ab").join("cd").join(IDENT).is_file());
+        assert_eq!(provider.list_files().await.unwrap(), [IDENT]);
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn stored_file_is_read_back() {
+        let dir = temp_dir();
+        let provider = provider(&dir, None).await;
+
+        provider
+            .store_file(IDENT.to_string(), content(b"contents"))
+            .await
+            .unwrap();
+
+        assert!(provider.file_exists(IDENT.to_string()).await.unwrap());
+        assert_eq!(read(&provider, IDENT).await, b"contents");
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn failed_write_leaves_no_partial_file() {
+        let dir = temp_dir();
+        let provider = provider(&dir, None).await;
+
+        let chunks = [
+            Ok(Bytes::from_static(b"partial")),
+            Err(std::io::Error::other("interrupted")),
+        ];
+        let interrupted = FileStream::new(64, stream::iter(chunks).boxed());
+        assert!(provider
+            .store_file(IDENT.to_string(), interrupted)
+            .await
+            .is_err());
+
+        assert!(!provider.file_exists(IDENT.to_string()).await.unwrap());
+        let mut temp_files = fs::read_dir(dir.join(TEMP_DIR)).await.unwrap();
+        assert!(temp_files.next_entry().await.unwrap().is_none());
+        assert_eq!(provider.used_storage(), 0);
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn deleted_file_is_gone() {
+        let dir = temp_dir();
+        let provider = provider(&dir, None).await;
+
+        provider
+            .store_file(IDENT.to_string(), content(b"contents"))
+            .await
+            .unwrap();
+        provider.delete_file(IDENT.to_string()).await.unwrap();
+
+        assert!(!provider.file_exists(IDENT.to_string()).await.unwrap());
+        assert!(provider.list_files().await.unwrap().is_empty());
+        assert_eq!(provider.used_storage(), 0);
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn used_storage_is_tracked_and_persisted() {
+        let dir = temp_dir();
+        let provider = provider(&dir, Some(1)).await;
+        let other = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+        provider
+            .store_file(IDENT.to_string(), content(b"contents"))
+            .await
+            .unwrap();
+        provider
+            .store_file(other.to_string(), content(b"other"))
+            .await
+            .unwrap();
+        // Replacing a file only counts the new contents
+        provider
+            .store_file(IDENT.to_string(), content(b"new"))
+            .await
+            .unwrap();
+        assert_eq!(provider.used_storage(), 8);
+
+        // Files beyond the limit of 1 MB are rejected
+        let too_large = FileStream::from_bytes(Bytes::from(vec![0; 1_000_000]), None).unwrap();
+        assert!(matches!(
+            provider.store_file(IDENT.to_string(), too_large).await,
+            Err(Error::StorageLimitExceeded)
+        ));
+
+        // The usage is read from the usage file instead of scanning the directory
+        drop(provider);
+        let provider = self::provider(&dir, Some(1)).await;
+        assert_eq!(provider.used_storage(), 8);
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+}
@@ -1,113 +1,215 @@
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
 
 use async_trait::async_trait;
-use bincache::{Cache, CacheBuilder, DiskStrategy, Noop};
+use futures::StreamExt;
 use hdrop_shared::{
     env,
     metrics::{names, UpdateMetrics},
 };
+use tokio::{fs, io::AsyncWriteExt};
+
+use super::provider::{Fetchtype, FileStream, StorageProvider};
+use crate::{core::ByteRange, error::Error, utils::mb_to_bytes, Result};
 
-use super::provider::{Fetchtype, StorageProvider};
-use crate::{error::Error, utils::mb_to_bytes, Result};
+/// Suffix of files which are still being written.
+const PARTIAL_SUFFIX: &str = ".part";
 
 #[derive(Debug)]
 pub struct LocalProvider {
-    storage: Cache<String, DiskStrategy, Noop>,
+    storage_path: PathBuf,
+    storage_limit: Option<u64>,
+    used_storage: AtomicU64,
 }
 
 impl LocalProvider {
-    pub async fn try_from_env() -> Result<Self> {
-        let storage_path = env::local_storage_dir().unwrap_or_else(|_| PathBuf::from("files"));
-        let storage_limit_mb = env::local_storage_limit_mb().ok().map(mb_to_bytes);
+    /// Create the provider from the `LOCAL_*` environment variables.
+    /// Named instances read prefixed variables, see [env::instance_var].
+    pub async fn try_from_env(instance: Option<&str>) -> Result<Self> {
+        let storage_path = Self::storage_dir_from_env(instance);
+        let storage_limit = env::instance_var(instance, "LOCAL_STORAGE_LIMIT_MB")
+            .ok()
+            .map(|limit| mb_to_bytes(limit) as u64);
+
+        fs::create_dir_all(&storage_path).await?;
+        let used_storage = Self::recover(&storage_path).await?;
+
+        Ok(Self {
+            storage_path,
+            storage_limit,
+            used_storage: AtomicU64::new(used_storage),
+        })
+    }
+
+    /// Directory holding the stored files.
+    pub fn storage_dir_from_env(instance: Option<&str>) -> PathBuf {
+        env::instance_var(instance, "LOCAL_STORAGE_DIR").unwrap_or_else(|_| PathBuf::from("files"))
+    }
+
+    /// Determine the used storage from the previous session and remove partially written files.
+    async fn recover(storage_path: &Path) -> Result<u64> {
+        let mut used_storage = 0;
+        let mut upload_dir = fs::read_dir(storage_path).await?;
+
+        while let Some(dir_entry) = upload_dir.next_entry().await? {
+            let metadata = dir_entry.metadata().await?;
+            if !metadata.is_file() {
+                tracing::warn!("Subfolder found in upload directory. You should check this manually, someone might have changed the upload directory");
+                continue;
+            }
+
+            if dir_entry
+                .file_name()
+                .to_string_lossy()
+                .ends_with(PARTIAL_SUFFIX)
+            {
+                tracing::debug!("Removing partially written file {:?}", dir_entry.path());
+                fs::remove_file(dir_entry.path()).await?;
+                continue;
+            }
+
+            used_storage += metadata.len();
+        }
+
+        Ok(used_storage)
+    }
+
+    fn path(&self, ident: &str) -> PathBuf {
+        self.storage_path.join(ident)
+    }
+
+    fn used_storage(&self) -> u64 {
+        self.used_storage.load(Ordering::Relaxed)
+    }
+
+    /// Subtract the size of a removed or replaced file from the used storage.
+    fn release_storage(&self, size: u64) {
+        let _ = self
+            .used_storage
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(size))
+            });
+    }
+
+    fn exceeds_limit(&self, additional_bytes: u64) -> bool {
+        self.storage_limit
+            .is_some_and(|limit| self.used_storage() + additional_bytes > limit)
+    }
+
+    /// Stream the content into a temporary file. Returns the number of bytes written.
+    async fn write_partial(&self, partial_path: &Path, mut content: FileStream) -> Result<u64> {
+        let mut file = fs::File::create(partial_path).await?;
+        let mut written = 0;
 
-        let mut cache = CacheBuilder
-            .with_strategy(DiskStrategy::new(storage_path, storage_limit_mb, None))
-            .build()
-            .await?;
+        while let Some(data) = content.stream.next().await {
+            let data = data?;
+            written += data.len() as u64;
+            if self.exceeds_limit(written) {
+                return Err(Error::StorageLimitExceeded);
+            }
+            file.write_all(&data).await?;
+        }
 
-        cache.recover(|s| Some(s.to_string())).await?;
+        file.flush().await?;
+        file.sync_all().await?;
 
-        Ok(Self { storage: cache })
+        Ok(written)
     }
 }
 
 #[async_trait]
 impl StorageProvider for LocalProvider {
-    async fn store_file(&mut self, ident: String, content: &[u8]) -> Result<Option<String>> {
-        self.storage.put(ident, content).await?;
+    async fn store_file(&self, ident: String, content: FileStream) -> Result<Option<String>> {
+        if self.exceeds_limit(content.size) {
+            return Err(Error::StorageLimitExceeded);
+        }
+
+        // Write to a temporary file first, so a failed write never leaves a truncated file behind
+        let path = self.path(&ident);
+        let partial_path = self.path(&format!("{ident}{PARTIAL_SUFFIX}"));
+        let written = match self.write_partial(&partial_path, content).await {
+            Ok(written) => written,
+            Err(err) => {
+                let _ = fs::remove_file(&partial_path).await;
+                return Err(err);
+            }
+        };
+
+        // Replace an existing file with the same ident
+        if let Ok(metadata) = fs::metadata(&path).await {
+            self.release_storage(metadata.len());
+        }
+        fs::rename(&partial_path, &path).await?;
+        self.used_storage.fetch_add(written, Ordering::Relaxed);
+
         // Action-based update of metrics due to write operation
         self.update_metrics().await;
         Ok(None)
     }
 
-    async fn delete_file(&mut self, ident: String) -> Result<()> {
-        self.storage.delete(ident).await?;
+    async fn delete_file(&self, ident: String) -> Result<()> {
+        let path = self.path(&ident);
+        let size = fs::metadata(&path).await?.len();
+        fs::remove_file(&path).await?;
+        self.release_storage(size);
+
         // Action-based update of metrics due to write operation
         self.update_metrics().await;
         Ok(())
     }
 
     async fn get_file(&self, ident: String) -> Result<Fetchtype> {
-        let data = self.storage.get(ident).await?;
-        Ok(Fetchtype::FileData(data.into_owned()))
+        let file = fs::File::open(self.path(&ident)).await?;
+        Ok(Fetchtype::FileStream(
+            FileStream::from_file(file, None).await?,
+        ))
+    }
+
+    async fn get_file_range(&self, ident: String, range: ByteRange) -> Result<Fetchtype> {
+        let file = fs::File::open(self.path(&ident)).await?;
+        Ok(Fetchtype::FileStream(
+            FileStream::from_file(file, Some(range)).await?,
+        ))
     }
 
     async fn file_exists(&self, ident: String) -> Result<bool> {
-        Ok(self.storage.exists(ident))
+        Ok(fs::try_exists(self.path(&ident)).await?)
     }
-}
 
-#[async_trait]
-impl UpdateMetrics for LocalProvider {
-    async fn update_metrics(&self) {
-        async fn used_storage() -> Option<Result<u64>> {
-            use tokio::fs::read_dir;
+    async fn health_check(&self) -> Result<()> {
+        if self
+            .storage_limit
+            .is_some_and(|limit| self.used_storage() >= limit)
+        {
+            return Err(Error::StorageLimitExceeded);
+        }
 
-            let upload_dir =
-                read_dir(env::local_storage_dir().unwrap_or_else(|_| PathBuf::from("files"))).await;
+        // Fails if the storage directory is missing or inaccessible
+        fs::read_dir(&self.storage_path).await?;
+        Ok(())
+    }
 
-            let mut upload_dir = match upload_dir {
-                Ok(read_dir) => read_dir,
-                Err(e) => {
-                    tracing::error!("Io Error: {}", e);
-                    return Some(Err(Error::Io(e)));
-                }
-            };
+    async fn list_files(&self) -> Result<Vec<String>> {
+        let mut idents = Vec::new();
+        let mut upload_dir = fs::read_dir(&self.storage_path).await?;
 
-            {
-                let mut used_storage = 0;
-
-                while let Some(dir_entry) = match upload_dir.next_entry().await {
-                    Ok(dir_entry) => dir_entry,
-                    Err(e) => {
-                        tracing::error!("Io Error: {}", e);
-                        return Some(Err(Error::Io(e)));
-                    }
-                } {
-                    let file = match dir_entry.metadata().await {
-                        Ok(metadata) => metadata,
-                        Err(e) => {
-                            tracing::error!("Io Error: {}", e);
-                            return Some(Err(Error::Io(e)));
-                        }
-                    };
-                    let file_size = if file.is_file() {
-                        file.len()
-                    } else {
-                        tracing::warn!("Subfolder found in upload directory. You should check this manually. Possible recovery folder from bincache or someone changed the upload directory");
-                        0
-                    };
-
-                    used_storage += file_size;
-                }
-
-                Some(Ok(used_storage))
+        while let Some(dir_entry) = upload_dir.next_entry().await? {
+            let name = dir_entry.file_name().to_string_lossy().to_string();
+            if dir_entry.metadata().await?.is_file() && !name.ends_with(PARTIAL_SUFFIX) {
+                idents.push(name);
             }
         }
 
-        let used_storage = used_storage().await.and_then(|s| s.ok()).unwrap_or(0);
+        Ok(idents)
+    }
+}
 
+#[async_trait]
+impl UpdateMetrics for LocalProvider {
+    async fn update_metrics(&self) {
         // Update storage gauge
-        metrics::gauge!(names::storage::USED_STORAGE_B, used_storage as f64);
+        metrics::gauge!(names::storage::USED_STORAGE_B, self.used_storage() as f64);
     }
 }
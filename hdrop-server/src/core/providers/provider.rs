@@ -1,24 +1,181 @@
+use std::io::SeekFrom;
+
 use async_trait::async_trait;
+use axum::body::Bytes;
+use futures::{
+    future::ready,
+    stream::{self, BoxStream},
+    StreamExt,
+};
 use hdrop_shared::metrics::UpdateMetrics;
+use tokio::{
+    fs,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
 
-use crate::Result;
+use crate::{
+    core::{ByteRange, ContentRange},
+    error::Error,
+    Result,
+};
 
 /// StorageProvider trait defining all functions which a Storage needs to implemented.
 /// Each StorageProvider must also implement [StorageMetrics]. If this is not possible, the default must be implemented.
+///
+/// Providers are shared between requests and workers without a lock, so transfers run concurrently.
+/// Mutable state, like the used storage, must be synchronized by the provider itself.
 #[async_trait]
 pub trait StorageProvider: UpdateMetrics {
     // String => impl as ref str
     /// Stores the file to the specified StorageProvider. E.g., uploads it to s3 or stores it local directly on the disk.
-    async fn store_file(&mut self, ident: String, content: &[u8]) -> Result<Option<String>>;
+    /// The content is consumed as a stream, so providers must not buffer the whole file in memory.
+    async fn store_file(&self, ident: String, content: FileStream) -> Result<Option<String>>;
     /// Deletes the file from the specified StorageProvider.
-    async fn delete_file(&mut self, ident: String) -> Result<()>;
+    async fn delete_file(&self, ident: String) -> Result<()>;
     /// Gets the file from the specified StorageProvider. E.g., fetches a download link or gets a datastream directly.
     async fn get_file(&self, ident: String) -> Result<Fetchtype>;
+    /// Gets a byte range of the file from the specified StorageProvider.
+    /// By default the preceding bytes of [StorageProvider::get_file] are skipped, providers which can seek should override this.
+    async fn get_file_range(&self, ident: String, range: ByteRange) -> Result<Fetchtype> {
+        Ok(match self.get_file(ident).await? {
+            Fetchtype::FileStream(file) => Fetchtype::FileStream(file.slice(range)?),
+            fetchtype => fetchtype,
+        })
+    }
+    /// Reads the contents of the file as stream, even if the provider hands out download URLs.
+    /// Used to copy files between providers.
+    async fn read_file(&self, ident: String) -> Result<FileStream> {
+        match self.get_file(ident).await? {
+            Fetchtype::FileStream(file) => Ok(file),
+            Fetchtype::FileUrl(_) => Err(Error::InvalidFile),
+        }
+    }
     /// Check if a file exists.
     async fn file_exists(&self, ident: String) -> Result<bool>;
+    /// Lists the idents of all stored files. Used to find files without a database entry.
+    /// Providers which cannot enumerate their files return [Error::ListingUnsupported].
+    async fn list_files(&self) -> Result<Vec<String>> {
+        Err(Error::ListingUnsupported)
+    }
+    /// Check that the provider is reachable and accepts files, without transferring file contents.
+    /// Called by the readiness check, so it must be cheap.
+    async fn health_check(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Remove leftovers of interrupted operations, e.g. abandoned multipart uploads.
+    /// Called periodically by the expiration worker.
+    async fn cleanup(&self) -> Result<()> {
+        Ok(())
+    }
+    /// Restore missing copies of stored files. Returns the number of restored copies.
+    /// Called periodically by the storage synchronizer.
+    async fn repair(&self) -> Result<usize> {
+        Ok(0)
+    }
+}
+
+/// Stream of file contents.
+pub type ByteStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// File contents together with their size in bytes.
+pub struct FileStream {
+    /// Number of bytes in the stream.
+    pub size: u64,
+    pub stream: ByteStream,
+    /// Part of the file covered by the stream, if only a range was requested.
+    pub range: Option<ContentRange>,
+}
+
+impl FileStream {
+    pub fn new(size: u64, stream: ByteStream) -> Self {
+        Self {
+            size,
+            stream,
+            range: None,
+        }
+    }
+
+    /// Stream the given range of an in-memory file.
+    pub fn from_bytes(data: Bytes, range: Option<ByteRange>) -> Result<Self> {
+        let (data, range) = match range {
+            Some(range) => {
+                let range = range.resolve(data.len() as u64)?;
+                (
+                    data.slice(range.start as usize..=range.end as usize),
+                    Some(range),
+                )
+            }
+            None => (data, None),
+        };
+
+        Ok(Self {
+            size: data.len() as u64,
+            stream: stream::once(ready(Ok(data))).boxed(),
+            range,
+        })
+    }
+
+    /// Stream the given range of a file on disk.
+    pub async fn from_file(mut file: fs::File, range: Option<ByteRange>) -> Result<Self> {
+        let size = file.metadata().await?.len();
+
+        match range {
+            Some(range) => {
+                let range = range.resolve(size)?;
+                file.seek(SeekFrom::Start(range.start)).await?;
+
+                Ok(Self {
+                    size: range.length(),
+                    stream: ReaderStream::new(file.take(range.length())).boxed(),
+                    range: Some(range),
+                })
+            }
+            None => Ok(Self::new(size, ReaderStream::new(file).boxed())),
+        }
+    }
+
+    /// Restrict the stream to the given range by skipping and truncating chunks.
+    pub fn slice(self, range: ByteRange) -> Result<Self> {
+        let range = range.resolve(self.size)?;
+
+        let stream = self
+            .stream
+            .scan(0u64, move |position, chunk| {
+                let chunk = match chunk {
+                    Ok(chunk) => chunk,
+                    Err(err) => return ready(Some(Some(Err(err)))),
+                };
+
+                let chunk_start = *position;
+                let chunk_length = chunk.len() as u64;
+                *position += chunk_length;
+
+                // Stop reading once the range is complete
+                if chunk_start > range.end {
+                    return ready(None);
+                }
+
+                let from = range.start.saturating_sub(chunk_start).min(chunk_length);
+                let to = (range.end + 1 - chunk_start).min(chunk_length);
+                if from >= to {
+                    ready(Some(None))
+                } else {
+                    ready(Some(Some(Ok(chunk.slice(from as usize..to as usize)))))
+                }
+            })
+            .filter_map(ready)
+            .boxed();
+
+        Ok(Self {
+            size: range.length(),
+            stream,
+            range: Some(range),
+        })
+    }
 }
 
 pub enum Fetchtype {
-    FileData(Vec<u8>),
+    FileStream(FileStream),
     FileUrl(String),
 }
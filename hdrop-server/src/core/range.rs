@@ -0,0 +1,85 @@
+use std::fmt::Display;
+
+use crate::{error::Error, Result};
+
+/// Byte range requested via the `Range` header, not yet resolved against the size of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// `bytes=start-end`
+    FromTo(u64, u64),
+    /// `bytes=start-`
+    From(u64),
+    /// `bytes=-length`
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Parse a `Range` header value.
+    /// Only a single byte range is supported. Anything else yields `None`, in which case the whole file is served.
+    pub fn parse(value: &str) -> Option<Self> {
+        let spec = value.trim().strip_prefix("bytes=")?;
+        // Multiple ranges are not supported
+        if spec.contains(',') {
+            return None;
+        }
+
+        let (start, end) = spec.trim().split_once('-')?;
+        match (start.is_empty(), end.is_empty()) {
+            (false, false) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(Self::FromTo(start, end))
+            }
+            (false, true) => Some(Self::From(start.parse().ok()?)),
+            (true, false) => Some(Self::Last(end.parse().ok()?)),
+            (true, true) => None,
+        }
+    }
+
+    /// Resolve the range against the size of a file.
+    pub fn resolve(self, size: u64) -> Result<ContentRange> {
+        let (start, end) = match self {
+            Self::FromTo(start, end) => (start, end.min(size.saturating_sub(1))),
+            Self::From(start) => (start, size.saturating_sub(1)),
+            Self::Last(length) if length > 0 => {
+                (size.saturating_sub(length), size.saturating_sub(1))
+            }
+            Self::Last(_) => return Err(Error::RangeNotSatisfiable { size }),
+        };
+
+        if size == 0 || start >= size {
+            return Err(Error::RangeNotSatisfiable { size });
+        }
+
+        Ok(ContentRange { start, end, size })
+    }
+}
+
+/// Resolved byte range of a file, as reported via the `Content-Range` header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContentRange {
+    /// First byte of the range.
+    pub start: u64,
+    /// Last byte of the range (inclusive).
+    pub end: u64,
+    /// Size of the complete file.
+    pub size: u64,
+}
+
+impl ContentRange {
+    /// Number of bytes in the range.
+    pub fn length(&self) -> u64 {
+        self.end - self.start + 1
+    }
+
+    /// Whether the range includes the first or the last byte of the file.
+    /// A file can only be fetched in parts by requesting both, so such ranges count as download.
+    pub fn includes_edge(&self) -> bool {
+        self.start == 0 || self.end + 1 == self.size
+    }
+}
+
+impl Display for ContentRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "bytes {}-{}/{}", self.start, self.end, self.size)
+    }
+}
@@ -1,6 +1,11 @@
 use std::{io::Error as StdError, net::AddrParseError};
 
-use axum::{extract::multipart::MultipartError, http::StatusCode, response::IntoResponse, Json};
+use axum::{
+    extract::multipart::MultipartError,
+    http::{header, HeaderValue, StatusCode},
+    response::IntoResponse,
+    Json,
+};
 use bincache::Error as BincacheError;
 use hdrop_shared::ErrorResponse;
 use regex::Error as RegexError;
@@ -19,6 +24,18 @@ pub enum Error {
     InvalidProvider(String),
     #[error("No Storage provider given")]
     NoProvider,
+    #[error("Invalid replication policy: {0}")]
+    InvalidReplicationPolicy(String),
+    #[error("Only {stored} of {required} required replicas could be stored")]
+    ReplicationQuorum { stored: usize, required: usize },
+    #[error("Migrated file {0} could not be found on the target provider")]
+    MigrationVerification(String),
+    #[error("A storage migration is already running")]
+    MigrationRunning,
+    #[error("Storage provider does not support listing files")]
+    ListingUnsupported,
+    #[error("Invalid reconciliation policy: {0}")]
+    InvalidReconciliationPolicy(String),
     // S3
     #[error("S3 error: {0}")]
     S3(#[from] S3Error),
@@ -32,9 +49,21 @@ pub enum Error {
     InvalidHeaderValue(#[from] axum::http::header::InvalidHeaderValue),
     #[error("Invalid CORS origin: {0}")]
     InvalidCorsOrigin(String),
+    // Metrics
+    #[error("Invalid metrics mode: {0}")]
+    InvalidMetricsMode(String),
+    #[error("Metrics setup failed: {0}")]
+    MetricsSetup(#[from] metrics_exporter_prometheus::BuildError),
+    // Tracing
+    #[error("Invalid log format: {0}")]
+    InvalidLogFormat(String),
+    #[error("Tracing setup failed: {0}")]
+    Tracing(#[from] opentelemetry::trace::TraceError),
     // Local
     #[error("I/O Error: {0}")]
     Io(#[from] StdError),
+    #[error("Local storage limit exceeded")]
+    StorageLimitExceeded,
     // Webserver
     #[error("{0}")]
     Multipart(#[from] MultipartError),
@@ -42,14 +71,46 @@ pub enum Error {
     FileDataConversionError(&'static str),
     #[error("Challenge failed")]
     InvalidChallenge,
+    #[error("File locked after too many failed challenges")]
+    ChallengeLockout,
+    #[error("Too many requests, retry after {retry_after} seconds")]
+    RateLimited { retry_after: u64 },
     #[error("Wrong update token")]
     UpdateToken,
+    #[error("Wrong admin token")]
+    AdminToken,
+    #[error("Wrong metrics credentials")]
+    MetricsAuth,
     #[error("Invalid Expiry")]
     InvalidExpiry,
+    #[error("Invalid expiry policy: {0}")]
+    InvalidExpiryPolicy(String),
+    #[error("Invalid download limit")]
+    InvalidDownloadLimit,
+    #[error("Download limit reached")]
+    DownloadLimitReached,
     #[error("Unable to locate file data")]
     InvalidFile,
+    #[error("Requested range not satisfiable for file of size {size}")]
+    RangeNotSatisfiable { size: u64 },
+    #[error("Missing or invalid upload header: {0}")]
+    InvalidUploadHeader(axum::http::HeaderName),
+    #[error("Upload offset does not match the stored offset")]
+    UploadOffsetMismatch,
+    #[error("Upload exceeds its declared length or the size limit")]
+    UploadTooLarge,
+    #[error("Upload is not complete yet")]
+    UploadIncomplete,
+    #[error("Upload is locked by another request")]
+    UploadLocked,
+    #[error("Upload was already finalized")]
+    UploadFinalized,
+    #[error("Server is shutting down")]
+    ShuttingDown,
     #[error("File upload failed: {reason}")]
     FileUpload { reason: String },
+    #[error("Invalid arguments: {0}")]
+    InvalidArguments(String),
     #[error("Socket could not get parsed: {0}")]
     SocketParse(#[from] AddrParseError),
     #[error("Storage provider deletion failed")]
@@ -74,8 +135,25 @@ impl Error {
             Self::FileUpload { .. } => StatusCode::BAD_REQUEST,
             Self::FileDataConversionError(_) => StatusCode::BAD_REQUEST,
             Self::InvalidChallenge => StatusCode::UNAUTHORIZED,
+            Self::ChallengeLockout => StatusCode::FORBIDDEN,
+            Self::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
             Self::UpdateToken => StatusCode::UNAUTHORIZED,
+            Self::AdminToken => StatusCode::UNAUTHORIZED,
+            Self::MetricsAuth => StatusCode::UNAUTHORIZED,
             Self::InvalidExpiry => StatusCode::BAD_REQUEST,
+            Self::InvalidDownloadLimit => StatusCode::BAD_REQUEST,
+            Self::DownloadLimitReached => StatusCode::GONE,
+            Self::RangeNotSatisfiable { .. } => StatusCode::RANGE_NOT_SATISFIABLE,
+            Self::InvalidUploadHeader(_) => StatusCode::BAD_REQUEST,
+            Self::UploadOffsetMismatch => StatusCode::CONFLICT,
+            Self::UploadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::UploadIncomplete => StatusCode::CONFLICT,
+            Self::UploadLocked => StatusCode::LOCKED,
+            Self::UploadFinalized => StatusCode::CONFLICT,
+            Self::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
+            Self::InvalidArguments(_) => StatusCode::BAD_REQUEST,
+            Self::InvalidProvider(_) => StatusCode::BAD_REQUEST,
+            Self::MigrationRunning => StatusCode::CONFLICT,
             Self::Database(e) if e.is_not_found() => StatusCode::NOT_FOUND,
             _ => StatusCode::INTERNAL_SERVER_ERROR,
         }
@@ -87,9 +165,26 @@ impl IntoResponse for Error {
         let reason = match &self {
             Self::FileUpload { .. } => "File upload failed",
             Self::InvalidChallenge => "Challenge failed",
+            Self::ChallengeLockout => "File locked after too many failed challenges",
+            Self::RateLimited { .. } => "Too many requests",
             Self::UpdateToken => "Wrong update token",
+            Self::AdminToken => "Wrong admin token",
+            Self::MetricsAuth => "Wrong metrics credentials",
             Self::InvalidExpiry => "Invalid Expiry",
+            Self::InvalidDownloadLimit => "Invalid download limit",
+            Self::DownloadLimitReached => "Download limit reached",
             Self::InvalidFile => "Unable to locate file data",
+            Self::RangeNotSatisfiable { .. } => "Requested range not satisfiable",
+            Self::InvalidUploadHeader(_) => "Missing or invalid upload header",
+            Self::UploadOffsetMismatch => "Upload offset mismatch",
+            Self::UploadTooLarge => "Upload too large",
+            Self::UploadIncomplete => "Upload incomplete",
+            Self::UploadLocked => "Upload is locked by another request",
+            Self::UploadFinalized => "Upload was already finalized",
+            Self::ShuttingDown => "Server is shutting down, retry later",
+            Self::InvalidArguments(_) => "Invalid arguments",
+            Self::InvalidProvider(_) => "Invalid storage provider",
+            Self::MigrationRunning => "A storage migration is already running",
             Self::Database(e) if e.is_not_found() => "No file found for given access token",
             Self::ProviderDeletion | Self::CacheDeletion => "File deletion failed",
             Self::DatabaseDeletion => "File contents safely deleted, database could not delete additional metadata. This is safe, database will be purged automatically later",
@@ -99,6 +194,23 @@ impl IntoResponse for Error {
         let error = ErrorResponse::new(reason);
 
         tracing::error!("{:?}", self);
-        (self.to_statuscode(), Json(error)).into_response()
+        let mut response = (self.to_statuscode(), Json(error)).into_response();
+
+        // Unsatisfiable ranges must report the actual file size
+        if let Self::RangeNotSatisfiable { size } = self {
+            response.headers_mut().insert(
+                header::CONTENT_RANGE,
+                HeaderValue::from_str(&format!("bytes */{size}"))
+                    .expect("Content-Range header value is always valid"),
+            );
+        }
+
+        if let Self::RateLimited { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+
+        response
     }
 }
@@ -1,33 +1,24 @@
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
-
 mod background_workers;
 mod core;
 mod error;
 mod server;
+mod telemetry;
 mod utils;
 pub(crate) use self::{
     error::Result,
     server::{hdrop_server::Server, prometheus_metrics_server::PrometheusMetricsServer},
 };
 
-// Initialize global tracing subscriber
-fn setup_tracing() {
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "hdrop_server=debug,hdrop_db=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
-}
-
 #[tokio::main]
 async fn main() -> Result<()> {
+    // Load environment variables from .env file (for development).
+    // Loaded before tracing is initialized, as tracing is configured from env.
+    let dotenv = dotenvy::dotenv();
+
     // Initialize tracing as early as possible
-    setup_tracing();
+    telemetry::setup_tracing()?;
 
-    // Load environment variables from .env file (for development)
-    match dotenvy::dotenv() {
+    match dotenv {
         Ok(_) => tracing::info!("Loaded environment variables from .env file"),
         Err(err) => {
             tracing::warn!("Failed to load environment variables from .env file: {err}. \
@@ -35,9 +26,16 @@ async fn main() -> Result<()> {
         }
     }
 
+    // Install the metrics recorder before anything records metrics
+    let metrics = PrometheusMetricsServer::try_from_env()?;
+
     // Start the main and metrics server
-    tokio::spawn(PrometheusMetricsServer.run());
-    Server::new().await?.run().await?;
+    let result = match Server::new().await {
+        Ok(server) => server.run(metrics).await,
+        Err(err) => Err(err),
+    };
 
-    Ok(())
+    // Export remaining spans before exiting
+    telemetry::shutdown();
+    result
 }
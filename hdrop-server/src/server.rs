@@ -1,9 +1,14 @@
 pub mod hdrop_server;
 pub mod prometheus_metrics_server;
 
+mod admin_server;
 mod app_state;
 mod cache;
+mod health;
 mod multipart;
+mod rate_limit;
 mod routes;
+mod shutdown;
+mod spool;
 
-pub use cache::CacheVariant;
+pub use self::{cache::CacheVariant, spool::Spool};
@@ -0,0 +1,363 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    headers::{authorization::Bearer, Authorization},
+    http::{Request, StatusCode},
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    routing::{delete, get, post},
+    Json,
+    Router,
+    TypedHeader,
+};
+use chrono::Utc;
+use hdrop_shared::{
+    env,
+    requests::StorageMigrationData,
+    responses::{
+        AdminFileData,
+        CacheStatusData,
+        FlushCacheData,
+        StorageMigrationStatusData,
+        SyncJobData,
+        SyncQueueData,
+        SyncState,
+    },
+};
+use uuid::Uuid;
+
+use super::{app_state::AppState, routes::verify_secret};
+use crate::{
+    background_workers::{expiration_worker::ExpirationWorker, storage_migrator::StorageMigrator},
+    core::{create_provider, provider_names_from_env, StorageProvider},
+    error::Error,
+    Result,
+};
+
+/// Number of files or sync jobs listed at once, unless requested otherwise.
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+/// Number of files loaded from the database at once while flushing the cache.
+const FLUSH_BATCH_SIZE: i64 = 500;
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PageQuery {
+    offset: Option<i64>,
+    limit: Option<i64>,
+}
+
+impl PageQuery {
+    fn offset(&self) -> i64 {
+        self.offset.unwrap_or(0).max(0)
+    }
+
+    fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_SIZE)
+            .clamp(1, MAX_PAGE_SIZE)
+    }
+}
+
+/// API for operators to inspect and manage the instance, served on `ADMIN_ADDRESS`.
+/// Only started if `ADMIN_TOKEN` is set, which must be sent as bearer token with every request.
+pub struct AdminServer {
+    state: Arc<AppState>,
+    token: Arc<str>,
+}
+
+impl AdminServer {
+    pub fn from_env(state: Arc<AppState>) -> Option<Self> {
+        match env::admin_token() {
+            Ok(token) if !token.is_empty() => Some(Self {
+                state,
+                token: token.into(),
+            }),
+            _ => {
+                tracing::info!("Admin API disabled, set ADMIN_TOKEN to enable it");
+                None
+            }
+        }
+    }
+
+    fn router(&self) -> Router {
+        Router::new()
+            .route("/admin/files", get(list_files))
+            .route("/admin/files/:id", delete(delete_file))
+            .route("/admin/sweep", post(sweep))
+            .route("/admin/cache", get(get_cache_status).delete(flush_cache))
+            .route("/admin/sync", get(get_sync_queue))
+            .route(
+                "/admin/storage-migrations",
+                get(get_storage_migrations)
+                    .post(start_storage_migration)
+                    .delete(stop_storage_migration),
+            )
+            .route_layer(from_fn_with_state(self.token.clone(), require_admin_token))
+            .with_state(self.state.clone())
+    }
+
+    /// Run the admin server until the main server shuts down.
+    pub async fn run(self) {
+        // Only reachable from the host itself, unless configured otherwise
+        let addr = env::admin_address().unwrap_or(SocketAddr::from(([127, 0, 0, 1], 3002)));
+        let app = self.router();
+
+        // Start the server
+        tracing::info!("Admin API listening on {addr}");
+        let server_result = axum::Server::bind(&addr)
+            .serve(app.into_make_service())
+            .with_graceful_shutdown(self.state.shutdown.clone().cancelled_owned())
+            .await;
+
+        // If the server fails to start, log the error
+        if let Err(err) = server_result {
+            tracing::error!("Admin API failed to start: {err}");
+        }
+    }
+}
+
+/// Middleware rejecting requests without the admin token.
+async fn require_admin_token<B>(
+    State(token): State<Arc<str>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    match bearer {
+        Some(TypedHeader(bearer)) if verify_secret(bearer.token(), &token) => {
+            Ok(next.run(req).await)
+        }
+        _ => Err(Error::AdminToken),
+    }
+}
+
+fn expiration_worker(state: &AppState) -> ExpirationWorker {
+    ExpirationWorker::new(
+        state.provider.clone(),
+        state.database.clone(),
+        state.cache.clone(),
+        state.spool.clone(),
+        state.blob_lock.clone(),
+    )
+}
+
+/* Routes */
+async fn list_files(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<Vec<AdminFileData>>> {
+    let files = state
+        .database
+        .get_files(query.offset(), query.limit())
+        .await?;
+
+    let uuids = files.iter().map(|file| file.uuid).collect();
+    let jobs: HashMap<_, _> = state
+        .database
+        .get_sync_jobs_by_uuid(uuids)
+        .await?
+        .into_iter()
+        .map(|job| (job.uuid, job))
+        .collect();
+
+    let cache = state.cache.read().await;
+    let now = Utc::now();
+
+    let files = files
+        .into_iter()
+        .map(|file| {
+            let sync_state = match jobs.get(&file.uuid) {
+                None => SyncState::Synced,
+                Some(job) if job.attempts > 0 => SyncState::Retrying,
+                Some(_) => SyncState::Queued,
+            };
+
+            AdminFileData {
+                uuid: file.uuid.to_string(),
+                cached: cache.exists(file.uuid),
+                access_token: file.accessToken,
+                file_size: file.fileSize,
+                age_seconds: (now - file.createdAt).num_seconds(),
+                expires_in_seconds: (file.expiresAt - now).num_seconds(),
+                download_count: file.downloadCount,
+                sync_state,
+            }
+        })
+        .collect();
+
+    Ok(Json(files))
+}
+
+/// Delete a file by UUID or access token, regardless of its update token.
+async fn delete_file(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode> {
+    let file = match Uuid::parse_str(&id) {
+        Ok(uuid) => state.database.get_file_by_uuid(uuid).await?,
+        Err(_) => state.database.get_file_by_access_token(id).await?,
+    };
+
+    expiration_worker(&state).delete_file(file.uuid).await?;
+    tracing::info!("File {} deleted via admin API", file.uuid);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Delete expired files right away instead of waiting for the expiration worker.
+async fn sweep(State(state): State<Arc<AppState>>) -> StatusCode {
+    expiration_worker(&state).sweep().await;
+    StatusCode::NO_CONTENT
+}
+
+async fn get_cache_status(State(state): State<Arc<AppState>>) -> Json<CacheStatusData> {
+    let cache = state.cache.read().await;
+    let capacity = cache.capacity();
+
+    Json(CacheStatusData {
+        strategy: cache.strategy().to_string(),
+        total_bytes: capacity.as_ref().map(|capacity| capacity.total() as u64),
+        used_bytes: capacity.as_ref().map(|capacity| capacity.used() as u64),
+    })
+}
+
+/// Delete all cached files. Files are served from the spool or the storage provider afterwards.
+async fn flush_cache(State(state): State<Arc<AppState>>) -> Result<Json<FlushCacheData>> {
+    let mut deleted = 0;
+    let mut cursor = None;
+
+    loop {
+        let files = state
+            .database
+            .get_files_after(cursor, FLUSH_BATCH_SIZE)
+            .await?;
+        let Some(last) = files.last() else {
+            break;
+        };
+        cursor = Some((last.createdAt, last.uuid));
+
+        let mut cache = state.cache.write().await;
+        for file in files {
+            if cache.exists(file.uuid) {
+                cache.delete(file.uuid).await?;
+                deleted += 1;
+            }
+        }
+    }
+
+    tracing::info!("Cache flushed via admin API, {deleted} entries deleted");
+    Ok(Json(FlushCacheData { deleted }))
+}
+
+async fn get_sync_queue(
+    State(state): State<Arc<AppState>>,
+    Query(query): Query<PageQuery>,
+) -> Result<Json<SyncQueueData>> {
+    let (queued, oldest) = state.database.get_sync_queue_stats().await?;
+    let jobs = state
+        .database
+        .get_sync_jobs(query.offset(), query.limit())
+        .await?;
+    let now = Utc::now();
+
+    Ok(Json(SyncQueueData {
+        queued,
+        oldest_job_age_seconds: oldest.map(|created_at| (now - created_at).num_seconds()),
+        jobs: jobs
+            .into_iter()
+            .map(|job| SyncJobData {
+                uuid: job.uuid.to_string(),
+                attempts: job.attempts,
+                next_attempt_in_seconds: (job.runAt - now).num_seconds(),
+                leased: job
+                    .leasedUntil
+                    .is_some_and(|leased_until| leased_until > now),
+                last_error: job.lastError,
+            })
+            .collect(),
+    }))
+}
+
+async fn get_storage_migrations(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<StorageMigrationStatusData>>> {
+    let migrations = state.database.get_storage_migrations().await?;
+    let running = state.storage_migration.lock().await;
+    let now = Utc::now();
+
+    Ok(Json(
+        migrations
+            .into_iter()
+            .map(|migration| StorageMigrationStatusData {
+                running: running.as_ref().is_some_and(|running| {
+                    running.is_running()
+                        && running.source == migration.source
+                        && running.target == migration.target
+                }),
+                migrated: migration.migrated,
+                retrying: migration.retryUuids.len(),
+                updated_seconds_ago: (now - migration.updatedAt).num_seconds(),
+                source: migration.source,
+                target: migration.target,
+            })
+            .collect(),
+    ))
+}
+
+/// Start copying all files between two storage providers in the background.
+async fn start_storage_migration(
+    State(state): State<Arc<AppState>>,
+    Json(data): Json<StorageMigrationData>,
+) -> Result<StatusCode> {
+    let mut running = state.storage_migration.lock().await;
+    if running
+        .as_ref()
+        .is_some_and(|migration| migration.is_running())
+    {
+        return Err(Error::MigrationRunning);
+    }
+
+    let source = data.source.to_lowercase();
+    let target = data.target.to_lowercase();
+    let migrator = StorageMigrator::new(
+        source.clone(),
+        migration_provider(&state, &source).await?,
+        target.clone(),
+        migration_provider(&state, &target).await?,
+        state.database.clone(),
+        data.files_per_second,
+        state.shutdown.child_token(),
+    )?;
+    if data.reset {
+        migrator.reset().await?;
+    }
+
+    tracing::info!("Storage migration from {source} to {target} started via admin API");
+    *running = Some(migrator.spawn());
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Stop the running storage migration. Its progress is kept, so starting it again continues.
+async fn stop_storage_migration(State(state): State<Arc<AppState>>) -> StatusCode {
+    if let Some(migration) = state.storage_migration.lock().await.as_ref() {
+        migration.stop();
+        tracing::info!("Storage migration stopped via admin API");
+    }
+
+    StatusCode::NO_CONTENT
+}
+
+/// The provider the server runs on is shared with the migration, other providers get their own instance.
+async fn migration_provider(
+    state: &AppState,
+    name: &str,
+) -> Result<Arc<dyn StorageProvider + Sync + Send>> {
+    if provider_names_from_env()? == [name] {
+        return Ok(state.provider.clone());
+    }
+
+    Ok(Arc::from(
+        create_provider(name, state.database.clone()).await?,
+    ))
+}
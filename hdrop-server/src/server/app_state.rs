@@ -1,52 +1,104 @@
 use std::sync::Arc;
 
 use hdrop_db::Database;
-use hdrop_shared::env;
-use tokio::sync::{mpsc::Sender, RwLock};
+use tokio::sync::{Mutex, Notify, RwLock};
+use tokio_util::sync::CancellationToken;
 
-use super::cache::CacheVariant;
+use super::{cache::CacheVariant, rate_limit::RateLimits, spool::Spool};
 use crate::{
-    background_workers::storage_synchronizer::ProviderSyncEntry,
-    core::{LocalProvider, S3Provider, StorageProvider},
+    background_workers::storage_migrator::StorageMigrationHandle,
+    core::{
+        create_provider,
+        provider_names_from_env,
+        CompositeProvider,
+        ExpiryPolicy,
+        ReplicationPolicy,
+        StorageProvider,
+    },
     error::Error,
     Result,
 };
 
 pub struct AppState {
-    pub provider: Arc<RwLock<Box<dyn StorageProvider + Sync + Send>>>,
+    pub provider: Arc<dyn StorageProvider + Sync + Send>,
     pub database: Arc<Database>,
     pub cache: Arc<RwLock<CacheVariant>>,
-    provider_sync_tx: Sender<ProviderSyncEntry>,
+    pub spool: Arc<Spool>,
+    pub expiry_policy: ExpiryPolicy,
+    pub rate_limits: RateLimits,
+    /// Held for writing while an unreferenced blob is deleted and for reading while a blob is stored,
+    /// so contents uploaded again meanwhile are not deleted.
+    pub blob_lock: Arc<RwLock<()>>,
+    /// Storage migration started via the admin API, only one runs at a time.
+    pub storage_migration: Mutex<Option<StorageMigrationHandle>>,
+    /// Wakes the storage synchronizer when a sync job is queued.
+    pub sync_notify: Arc<Notify>,
+    /// Cancelled once the server received a shutdown signal.
+    pub shutdown: CancellationToken,
 }
 
 impl AppState {
-    pub async fn new(provider_sync_tx: Sender<ProviderSyncEntry>) -> Result<Self> {
-        let provider: Box<dyn StorageProvider + Sync + Send> = match env::storage_provider()
-            .map(|s| s.to_lowercase())
-        {
-            Ok(ref provider) if provider == "s3" => {
-                Ok(Box::new(S3Provider::try_from_env()?) as Box<dyn StorageProvider + Sync + Send>)
-            }
-            Ok(ref provider) if provider == "local" => {
-                Ok(Box::new(LocalProvider::try_from_env().await?)
-                    as Box<dyn StorageProvider + Sync + Send>)
-            }
-            Ok(provider) => Err(Error::InvalidProvider(provider)),
-            Err(_) => Err(Error::NoProvider),
-        }?;
-
+    pub async fn new() -> Result<Self> {
         let database = Arc::new(Database::try_from_env()?);
+        let provider = Self::provider_from_env(database.clone()).await?;
         let cache = Arc::new(RwLock::new(CacheVariant::try_from_env().await?));
+        let spool = Arc::new(Spool::try_from_env().await?);
+        let expiry_policy = ExpiryPolicy::try_from_env()?;
+        let rate_limits = RateLimits::from_env();
 
         Ok(AppState {
-            provider: Arc::new(RwLock::new(provider)),
+            provider: Arc::from(provider),
             database,
-            provider_sync_tx,
             cache,
+            spool,
+            expiry_policy,
+            rate_limits,
+            blob_lock: Arc::default(),
+            storage_migration: Mutex::default(),
+            sync_notify: Arc::new(Notify::new()),
+            shutdown: CancellationToken::new(),
         })
     }
 
-    pub fn get_provider_sync_tx(&self) -> Sender<ProviderSyncEntry> {
-        self.provider_sync_tx.clone()
+    /// Create the storage provider from `STORAGE_PROVIDER`.
+    /// A comma-separated list of providers, e.g. `local,s3@backup`, creates a [CompositeProvider] replicating to all of them.
+    async fn provider_from_env(
+        database: Arc<Database>,
+    ) -> Result<Box<dyn StorageProvider + Sync + Send>> {
+        let names = provider_names_from_env()?;
+
+        let mut providers = Vec::with_capacity(names.len());
+        for name in names {
+            let provider = create_provider(&name, database.clone()).await?;
+            providers.push((name, provider));
+        }
+
+        match providers.len() {
+            0 => Err(Error::NoProvider),
+            1 => Ok(providers.remove(0).1),
+            count => {
+                let policy = ReplicationPolicy::try_from_env(count)?;
+                tracing::info!("Replicating files to {count} storage providers ({policy:?})");
+                Ok(Box::new(CompositeProvider::new(
+                    providers, policy, database,
+                )))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// State for tests, keeping files in a temporary directory and using the database in `DATABASE_URL`.
+    pub async fn for_tests() -> Arc<Self> {
+        static ENV: std::sync::Once = std::sync::Once::new();
+        ENV.call_once(|| {
+            let dir = std::env::temp_dir().join(format!("hdrop-test-{}", uuid::Uuid::new_v4()));
+            std::env::set_var("STORAGE_PROVIDER", "filesystem");
+            std::env::set_var("FILESYSTEM_STORAGE_DIR", dir.join("storage"));
+            std::env::set_var("UPLOAD_SPOOL_DIR", dir.join("spool"));
+        });
+
+        Arc::new(Self::new().await.expect("DATABASE_URL must be set"))
     }
 }
@@ -15,12 +15,16 @@ use hdrop_shared::{
     env,
     metrics::{names, UpdateMetrics},
 };
+use tokio::fs;
 use uuid::Uuid;
 
 use crate::{utils::mb_to_bytes, Result};
 
 type FileCache<S> = Cache<Uuid, S, Noop>;
 
+/// Default size limit for a single cache entry.
+const DEFAULT_ENTRY_LIMIT_MB: usize = 8;
+
 pub enum CacheVariant {
     Memory(FileCache<MemoryStrategy>),
     Disk(FileCache<DiskStrategy>),
@@ -30,7 +34,7 @@ pub enum CacheVariant {
 impl CacheVariant {
     pub async fn try_from_env() -> Result<Self> {
         let cache_variant: String = env::cache_strategy().unwrap_or_else(|_| "memory".to_string());
-        let cache_dir = env::cache_dir().unwrap_or_else(|_| PathBuf::from("file_cache"));
+        let cache_dir = Self::dir_from_env();
         let memory_byte_limit = env::cache_memory_limit_mb().map(mb_to_bytes).ok();
         let disk_byte_limit = env::cache_disk_limit_mb().map(mb_to_bytes).ok();
 
@@ -64,6 +68,18 @@ impl CacheVariant {
         }
     }
 
+    /// Directory of the disk and hybrid cache strategies.
+    pub fn dir_from_env() -> PathBuf {
+        env::cache_dir().unwrap_or_else(|_| PathBuf::from("file_cache"))
+    }
+
+    /// Whether a file of the given size may be cached.
+    /// Cache entries are held in one piece, larger files are only streamed from the spool.
+    pub fn accepts(size: u64) -> bool {
+        let entry_limit = env::cache_entry_limit_mb().unwrap_or(DEFAULT_ENTRY_LIMIT_MB);
+        size <= mb_to_bytes(entry_limit) as u64
+    }
+
     #[tracing::instrument(skip_all, level = "trace")]
     pub async fn put(&mut self, key: Uuid, value: Vec<u8>) -> Result<()> {
         let result = match self {
@@ -113,6 +129,28 @@ impl CacheVariant {
         Ok(result)
     }
 
+    /// Sync the entries of the disk and hybrid strategies to disk, so they are recovered on the next start.
+    /// Holding `&mut self` ensures that no write is in progress. Entries held in memory are not persisted,
+    /// the spool keeps files until they are stored by the storage provider anyway.
+    #[tracing::instrument(skip_all, level = "trace")]
+    pub async fn flush(&mut self) -> Result<()> {
+        if let CacheVariant::Memory(_) = self {
+            return Ok(());
+        }
+
+        let dir = Self::dir_from_env();
+        let mut entries = fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.file_type().await?.is_file() {
+                fs::File::open(entry.path()).await?.sync_all().await?;
+            }
+        }
+
+        // Makes the creation of the entries durable
+        fs::File::open(&dir).await?.sync_all().await?;
+        Ok(())
+    }
+
     pub fn exists(&self, key: Uuid) -> bool {
         match self {
             CacheVariant::Disk(cache) => cache.exists(key),
@@ -121,6 +159,15 @@ impl CacheVariant {
         }
     }
 
+    /// Name of the cache strategy, as configured in `CACHE_STRATEGY`.
+    pub fn strategy(&self) -> &'static str {
+        match self {
+            CacheVariant::Disk(_) => "disk",
+            CacheVariant::Hybrid(_) => "hybrid",
+            CacheVariant::Memory(_) => "memory",
+        }
+    }
+
     pub fn capacity(&self) -> Option<CacheCapacity> {
         match self {
             CacheVariant::Disk(cache) => cache.capacity(),
@@ -152,3 +199,37 @@ impl UpdateMetrics for CacheVariant {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Hybrid cache without memory, so entries are written to disk right away.
+    async fn hybrid_cache() -> CacheVariant {
+        let strategy = HybridStrategy::new(
+            CacheVariant::dir_from_env(),
+            Limits::new(Some(0), None),
+            Limits::new(None, None),
+        );
+        CacheVariant::Hybrid(CacheBuilder.with_strategy(strategy).build().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn flushed_entry_is_recovered_after_reopening() {
+        let dir = std::env::temp_dir().join(format!("hdrop-cache-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).await.unwrap();
+        std::env::set_var("CACHE_DIR", &dir);
+        let key = Uuid::new_v4();
+
+        let mut cache = hybrid_cache().await;
+        cache.put(key, b"cached contents".to_vec()).await.unwrap();
+        cache.flush().await.unwrap();
+        drop(cache);
+
+        let mut cache = hybrid_cache().await;
+        assert_eq!(cache.recover().await.unwrap(), 1);
+        assert_eq!(cache.get(key).await.unwrap().as_ref(), b"cached contents");
+
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+}
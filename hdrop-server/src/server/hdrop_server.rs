@@ -1,16 +1,21 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{future::Future, net::SocketAddr, pin::Pin, sync::Arc};
 
 use axum::{
     extract::DefaultBodyLimit,
     http::HeaderValue,
-    middleware::from_fn,
-    routing::{get, post},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{get, head, post},
     Router,
 };
 use hdrop_shared::{env, metrics::UpdateMetrics};
-use tokio::sync::mpsc::{channel, Receiver};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 use tower_http::{
-    compression::CompressionLayer,
+    compression::{
+        predicate::{NotForContentType, Predicate},
+        CompressionLayer,
+        DefaultPredicate,
+    },
     cors::{AllowOrigin, Any, CorsLayer},
     limit::RequestBodyLimitLayer,
     trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer},
@@ -18,34 +23,43 @@ use tower_http::{
 use tracing::Level;
 
 use super::{
+    admin_server::AdminServer,
     app_state::AppState,
+    health,
+    prometheus_metrics_server::{MetricsMode, PrometheusMetricsServer},
+    rate_limit,
     routes::{
+        create_upload,
         delete_file,
+        delete_upload,
+        finalize_upload,
         get_challenge,
+        get_config,
+        get_expiry_policy,
         get_file,
+        get_upload_offset,
+        patch_upload,
+        update_download_limit,
         update_file_expiry,
         upload_file,
         verify_challenge,
     },
+    shutdown,
 };
 use crate::{
     background_workers::{
         expiration_worker::ExpirationWorker,
         metrics_middleware,
-        storage_synchronizer::{ProviderSyncEntry, StorageSynchronizer},
+        reconciliation_worker::ReconciliationWorker,
+        storage_synchronizer::StorageSynchronizer,
         MetricsUpdater,
     },
     error::{Error, Result},
     utils::mb_to_bytes,
 };
 
-/// Size of the provider synchronization channel.
-/// When the channel is full, the handler will block instead of accumulating unbounded memory.
-const PROVIDER_SYNC_CHANNEL_SIZE: usize = 512;
-
 pub struct Server {
     state: Arc<AppState>,
-    provider_sync_rx: Receiver<ProviderSyncEntry>,
 }
 
 impl Server {
@@ -90,13 +104,9 @@ impl Server {
     /// Create a new [Server] instance.
     pub async fn new() -> Result<Self> {
         // Initialize app state
-        let (provider_sync_tx, provider_sync_rx) = channel(PROVIDER_SYNC_CHANNEL_SIZE);
-        let state = Arc::new(AppState::new(provider_sync_tx).await?);
+        let state = Arc::new(AppState::new().await?);
 
-        Ok(Self {
-            state,
-            provider_sync_rx,
-        })
+        Ok(Self { state })
     }
 
     /// Run the main server.
@@ -104,16 +114,50 @@ impl Server {
     ///
     /// The following steps will be executed:
     /// 1. Upon running the server the cache will get recovered.
-    /// 2. Background workers will start for storage synchronization and expiration workers.
-    /// 3. Metrics will be initialized.
+    /// 2. Background workers will start for storage synchronization, reconciliation and expiration workers.
+    /// 3. Metrics will be initialized and served, either standalone or on the main router.
     /// 4. Lastly, the server will be initialized and started.
-    pub async fn run(self) -> Result<()> {
+    /// 5. On SIGINT or SIGTERM, the server reports not ready and stops accepting uploads.
+    ///    After `SHUTDOWN_DELAY_SECS`, it closes the listener and drains in-flight requests and sync jobs.
+    pub async fn run(self, metrics: PrometheusMetricsServer) -> Result<()> {
         // Recover cache from last session
         self.recover_cache().await;
 
         // Start storage synchronization worker.
         // This worker is responsible for synchronizing cached files with the storage provider.
-        tokio::spawn(StorageSynchronizer::new(self.provider_sync_rx).run());
+        // Sync jobs are persisted, so jobs queued before a restart are resumed.
+        let drain = CancellationToken::new();
+        let synchronizer = tokio::spawn(
+            StorageSynchronizer::new(
+                self.state.provider.clone(),
+                self.state.database.clone(),
+                self.state.cache.clone(),
+                self.state.spool.clone(),
+                self.state.blob_lock.clone(),
+                self.state.sync_notify.clone(),
+                drain.clone(),
+            )
+            .run(),
+        );
+
+        // Start storage repair worker.
+        // This worker is responsible for restoring missing copies when replicating to several providers.
+        tokio::spawn(StorageSynchronizer::repair_worker(
+            self.state.provider.clone(),
+        ));
+
+        // Start reconciliation worker.
+        // This worker is responsible for finding inconsistencies between database, cache, spool and storage provider.
+        tokio::spawn(
+            ReconciliationWorker::try_new(
+                self.state.provider.clone(),
+                self.state.database.clone(),
+                self.state.cache.clone(),
+                self.state.spool.clone(),
+                self.state.sync_notify.clone(),
+            )?
+            .run(),
+        );
 
         // Start expiration worker.
         // This worker is responsible for deleting expired files from the storage provider and cache.
@@ -122,6 +166,8 @@ impl Server {
                 self.state.provider.clone(),
                 self.state.database.clone(),
                 self.state.cache.clone(),
+                self.state.spool.clone(),
+                self.state.blob_lock.clone(),
             )
             .run(),
         );
@@ -134,32 +180,90 @@ impl Server {
         self.state.database.update_metrics().await;
 
         // Update storage metrics
-        self.state.provider.read().await.update_metrics().await;
+        self.state.provider.update_metrics().await;
 
         // Start metrics update worker for time-based updates of system gauges
         tokio::spawn(MetricsUpdater::new().run());
 
+        // Serve metrics on their own port, unless they are served on the main router below
+        let metrics_router = match metrics.mode() {
+            MetricsMode::Standalone => {
+                tokio::spawn(metrics.run());
+                None
+            }
+            MetricsMode::Main => Some(metrics.router()),
+            MetricsMode::Disabled => None,
+        };
+
+        // Start admin API, if an admin token is configured
+        if let Some(admin_server) = AdminServer::from_env(self.state.clone()) {
+            tokio::spawn(admin_server.run());
+        }
+
         // Calculate request body limit
         let request_body_limit_bytes = mb_to_bytes(env::single_file_limit_mb().unwrap_or(100));
 
-        // Define API routes
-        let app = Router::new()
-            .route("/status", get(|| async { "OK" }))
-            .route(
-                "/v1/files",
-                post(upload_file).layer(DefaultBodyLimit::max(request_body_limit_bytes)), // 256MB
-            )
+        // Routes accessing files by access token, rate limited against enumeration and challenge guessing
+        let file_routes = Router::new()
             .route("/v1/files/:access_token", get(get_file).delete(delete_file))
             .route("/v1/files/:access_token/expiry", post(update_file_expiry))
+            .route(
+                "/v1/files/:access_token/downloads",
+                post(update_download_limit),
+            )
             .route(
                 "/v1/files/:access_token/challenge",
                 get(get_challenge).post(verify_challenge),
             )
-            .with_state(self.state)
+            .route_layer(from_fn_with_state(
+                self.state.clone(),
+                rate_limit::limit_file_requests,
+            ));
+
+        // Routes starting new uploads, which are rejected once the server is shutting down
+        let upload_routes = Router::new()
+            .route(
+                "/v1/files",
+                post(upload_file).layer(DefaultBodyLimit::max(request_body_limit_bytes)), // 256MB
+            )
+            .route("/v1/files/uploads", post(create_upload))
+            .route_layer(from_fn_with_state(
+                self.state.clone(),
+                shutdown::reject_during_shutdown,
+            ));
+
+        // Define API routes
+        let state = self.state.clone();
+        let mut app: Router = Router::new()
+            .route("/status", get(|| async { "OK" }))
+            .route("/health/live", get(health::live))
+            .route("/health/ready", get(health::ready))
+            .route("/v1/config", get(get_config))
+            .route("/v1/config/expiry", get(get_expiry_policy))
+            .merge(upload_routes)
+            .route(
+                "/v1/files/uploads/:upload_id",
+                head(get_upload_offset)
+                    .patch(patch_upload)
+                    .delete(delete_upload),
+            )
+            .route(
+                "/v1/files/uploads/:upload_id/finalize",
+                post(finalize_upload),
+            )
+            .merge(file_routes)
+            .with_state(self.state);
+        if let Some(metrics_router) = metrics_router {
+            app = app.merge(metrics_router);
+        }
+        let app = app
             // Limit request body size
             .layer(RequestBodyLimitLayer::new(request_body_limit_bytes))
-            // Use brotli compression if applicable
-            .layer(CompressionLayer::new())
+            // Use brotli compression if applicable.
+            // Encrypted file data is incompressible and must keep its length for range requests.
+            .layer(CompressionLayer::new().compress_when(
+                DefaultPredicate::new().and(NotForContentType::new("application/octet-stream")),
+            ))
             .layer(
                 TraceLayer::new_for_http()
                     .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
@@ -171,7 +275,9 @@ impl Server {
                 CorsLayer::new()
                     .allow_origin(Self::cors_origin()?)
                     .allow_methods(Any)
-                    .allow_headers(Any),
+                    .allow_headers(Any)
+                    // Resumable upload offsets are reported via headers
+                    .expose_headers(Any),
             );
 
         // Server configuration
@@ -179,12 +285,83 @@ impl Server {
 
         tracing::info!("Starting server on {addr}");
 
+        // On a shutdown signal, report not ready and reject new uploads first.
+        // Connections are still accepted until load balancers stopped routing requests to this instance.
+        let shutdown = state.shutdown.clone();
+        let stop_listening = CancellationToken::new();
+        tokio::spawn({
+            let stop_listening = stop_listening.clone();
+            async move {
+                shutdown::signal().await;
+                shutdown.cancel();
+
+                let delay = shutdown::delay();
+                tracing::info!("Reporting not ready, closing the listener in {delay:?}");
+                tokio::time::sleep(delay).await;
+                stop_listening.cancel();
+            }
+        });
+
         // Start the server
-        axum::Server::bind(&addr)
-            .serve(app.into_make_service())
-            .await
-            .unwrap_or_else(|err| panic!("Server failed to start on {addr}: {err:?}"));
+        let server = axum::Server::bind(&addr)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown(stop_listening.clone().cancelled_owned());
+        tokio::pin!(server);
 
+        // The server only stops by itself on errors, as checking for the listener first never polls it after completion
+        tokio::select! {
+            biased;
+            _ = stop_listening.cancelled() => (),
+            result = &mut server => {
+                result.unwrap_or_else(|err| panic!("Server failed to start on {addr}: {err:?}"));
+            }
+        }
+
+        Self::drain(state, server, synchronizer, drain).await;
         Ok(())
     }
+
+    /// Finish in-flight requests and sync jobs within the shutdown timeout.
+    ///
+    /// The synchronizer is drained after the last request finished, so it also stores files of uploads completed meanwhile.
+    /// Sync jobs which are not finished in time are resumed on the next start.
+    async fn drain<F, E>(
+        state: Arc<AppState>,
+        server: Pin<&mut F>,
+        synchronizer: JoinHandle<Result<()>>,
+        drain: CancellationToken,
+    ) where
+        F: Future<Output = std::result::Result<(), E>>,
+        E: std::fmt::Debug,
+    {
+        let timeout = shutdown::timeout();
+        tracing::info!("Shutting down, draining for up to {timeout:?}");
+
+        let drained = tokio::time::timeout(timeout, async {
+            if let Err(err) = server.await {
+                tracing::error!("Server failed while shutting down: {err:?}");
+            }
+            tracing::info!("In-flight requests finished");
+
+            drain.cancel();
+            match synchronizer.await {
+                Ok(Err(err)) => tracing::error!("Storage synchronizer failed: {err}"),
+                Err(err) => tracing::error!("Storage synchronizer panicked: {err}"),
+                Ok(Ok(())) => (),
+            }
+
+            // Pending cache writes are finished first, so the hybrid cache recovers complete entries on the next start
+            if let Err(err) = state.cache.write().await.flush().await {
+                tracing::error!("Could not flush cache: {err}");
+            }
+        })
+        .await;
+
+        match drained {
+            Ok(()) => tracing::info!("Shutdown complete"),
+            Err(_) => tracing::warn!(
+                "Shutdown timed out, unfinished sync jobs are resumed on the next start"
+            ),
+        }
+    }
 }
@@ -0,0 +1,142 @@
+use std::{collections::BTreeMap, future::Future, sync::Arc, time::Duration};
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::Utc;
+use hdrop_shared::{
+    env,
+    responses::{ComponentHealthData, HealthData, HealthStatus},
+};
+
+use super::app_state::AppState;
+use crate::error::Error;
+
+/// Time after which an unanswered check counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+/// Cache usage from which the cache counts as degraded, unless configured otherwise.
+const DEFAULT_CACHE_MAX_USAGE_PERCENT: f64 = 95.0;
+/// Number of queued sync jobs from which the queue counts as degraded, unless configured otherwise.
+const DEFAULT_SYNC_MAX_QUEUED: i64 = 1000;
+/// Age of the oldest sync job from which the queue counts as degraded, unless configured otherwise.
+const DEFAULT_SYNC_MAX_AGE_SECS: i64 = 3600;
+
+fn component(status: HealthStatus, message: impl Into<String>) -> ComponentHealthData {
+    ComponentHealthData {
+        status,
+        message: Some(message.into()),
+    }
+}
+
+/// Run the check, failing it if it does not finish within [CHECK_TIMEOUT].
+async fn with_timeout<F>(check: F) -> ComponentHealthData
+where
+    F: Future<Output = ComponentHealthData>,
+{
+    tokio::time::timeout(CHECK_TIMEOUT, check)
+        .await
+        .unwrap_or_else(|_| component(HealthStatus::Down, "Check timed out"))
+}
+
+async fn check_database(state: &AppState) -> ComponentHealthData {
+    match state.database.ping().await {
+        Ok(()) => HealthStatus::Ok.into(),
+        Err(err) => component(HealthStatus::Down, err.to_string()),
+    }
+}
+
+/// Full storage only rejects new uploads, stored files are still served.
+async fn check_storage(state: &AppState) -> ComponentHealthData {
+    match state.provider.health_check().await {
+        Ok(()) => HealthStatus::Ok.into(),
+        Err(Error::StorageLimitExceeded) => component(HealthStatus::Degraded, "Storage is full"),
+        Err(err) => component(HealthStatus::Down, err.to_string()),
+    }
+}
+
+/// A full cache only slows down downloads, files are served from the spool or the storage provider.
+async fn check_cache(state: &AppState) -> ComponentHealthData {
+    let Some(capacity) = state.cache.read().await.capacity() else {
+        return HealthStatus::Ok.into();
+    };
+    if capacity.total() == 0 {
+        return HealthStatus::Ok.into();
+    }
+
+    let max_usage =
+        env::health_cache_max_usage_percent().unwrap_or(DEFAULT_CACHE_MAX_USAGE_PERCENT);
+    let usage = capacity.used() as f64 / capacity.total() as f64 * 100.0;
+
+    match usage >= max_usage {
+        true => component(HealthStatus::Degraded, format!("Cache is {usage:.0}% full")),
+        false => HealthStatus::Ok.into(),
+    }
+}
+
+/// A backlog only delays storing files, they are served from the spool meanwhile.
+async fn check_sync_queue(state: &AppState) -> ComponentHealthData {
+    let (queued, oldest) = match state.database.get_sync_queue_stats().await {
+        Ok(stats) => stats,
+        Err(err) => return component(HealthStatus::Down, err.to_string()),
+    };
+
+    let max_queued = env::health_sync_max_queued().unwrap_or(DEFAULT_SYNC_MAX_QUEUED);
+    let max_age = env::health_sync_max_age_secs().unwrap_or(DEFAULT_SYNC_MAX_AGE_SECS);
+    let age = oldest.map_or(0, |created_at| (Utc::now() - created_at).num_seconds());
+
+    if queued >= max_queued {
+        component(
+            HealthStatus::Degraded,
+            format!("{queued} files waiting for synchronization"),
+        )
+    } else if age >= max_age {
+        component(
+            HealthStatus::Degraded,
+            format!("Oldest file waiting for synchronization for {age} seconds"),
+        )
+    } else {
+        HealthStatus::Ok.into()
+    }
+}
+
+/* Routes */
+
+/// Answers as long as the server is running.
+pub async fn live() -> Json<ComponentHealthData> {
+    Json(HealthStatus::Ok.into())
+}
+
+/// Whether the server can serve requests.
+/// Answers with `503 Service Unavailable` if a component is down or the server is shutting down.
+pub async fn ready(State(state): State<Arc<AppState>>) -> (StatusCode, Json<HealthData>) {
+    let (database, storage, cache, sync_queue) = tokio::join!(
+        with_timeout(check_database(&state)),
+        with_timeout(check_storage(&state)),
+        with_timeout(check_cache(&state)),
+        with_timeout(check_sync_queue(&state)),
+    );
+
+    let mut components = BTreeMap::from([
+        ("database", database),
+        ("storage", storage),
+        ("cache", cache),
+        ("sync_queue", sync_queue),
+    ]);
+    // Load balancers stop sending requests, while in-flight requests are finished
+    if state.shutdown.is_cancelled() {
+        components.insert(
+            "server",
+            component(HealthStatus::Down, "Server is shutting down"),
+        );
+    }
+
+    let status = components
+        .values()
+        .map(|component| component.status)
+        .max()
+        .unwrap_or(HealthStatus::Ok);
+    let status_code = match status {
+        HealthStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::OK,
+    };
+
+    (status_code, Json(HealthData { status, components }))
+}
@@ -1,5 +1,7 @@
-use axum::{body::Bytes, extract::Multipart};
+use axum::extract::Multipart;
+use uuid::Uuid;
 
+use super::spool::Spool;
 use crate::error::{Error, Result};
 
 /// Initial struct which allows file data to be incomplete.
@@ -7,15 +9,24 @@ use crate::error::{Error, Result};
 pub struct PartialUploadedFile {
     iv: Option<String>,
     salt: Option<String>,
-    file_data: Option<Bytes>,
+    file_size: Option<u64>,
+    content_hash: Option<String>,
     file_name_data: Option<String>,
     challenge_data: Option<String>,
     challenge_hash: Option<String>,
+    max_downloads: Option<u32>,
+    expiry: Option<i64>,
     error: Option<String>,
 }
 
 impl PartialUploadedFile {
-    pub async fn from_multipart(mut multipart_formdata: Multipart) -> PartialUploadedFile {
+    /// Parse the multipart formdata. The file data is streamed into the spool under the given key.
+    pub async fn from_multipart(
+        mut multipart_formdata: Multipart,
+        spool: &Spool,
+        key: Uuid,
+        max_length: u64,
+    ) -> PartialUploadedFile {
         let mut partial_data: PartialUploadedFile = PartialUploadedFile::default();
 
         while let Some(field) = multipart_formdata.next_field().await.ok().flatten() {
@@ -29,9 +40,10 @@ impl PartialUploadedFile {
                 "salt" => {
                     partial_data.salt = field.text().await.ok();
                 }
-                "file_data" => match field.bytes().await {
-                    Ok(x) => {
-                        partial_data.file_data = Some(x);
+                "file_data" => match spool.write(key, max_length, Box::pin(field)).await {
+                    Ok(file) => {
+                        partial_data.file_size = Some(file.size);
+                        partial_data.content_hash = Some(file.hash);
                     }
                     Err(e) => {
                         partial_data.error = Some(format!("{:?}", e));
@@ -46,6 +58,14 @@ impl PartialUploadedFile {
                 "challenge_hash" => {
                     partial_data.challenge_hash = field.text().await.ok();
                 }
+                "max_downloads" => match field.text().await.map(|text| text.parse()) {
+                    Ok(Ok(max_downloads)) => partial_data.max_downloads = Some(max_downloads),
+                    _ => partial_data.error = Some("Invalid max_downloads".to_string()),
+                },
+                "expiry" => match field.text().await.map(|text| text.parse()) {
+                    Ok(Ok(expiry)) => partial_data.expiry = Some(expiry),
+                    _ => partial_data.error = Some("Invalid expiry".to_string()),
+                },
                 field => {
                     tracing::debug!("Unknown field: {field}")
                 }
@@ -61,10 +81,16 @@ impl PartialUploadedFile {
 pub struct UploadedFile {
     pub iv: String,
     pub salt: String,
-    pub file_data: Bytes,
+    pub file_size: u64,
+    /// SHA-256 hash of the file data, if it is known.
+    pub content_hash: Option<String>,
     pub file_name_data: String,
     pub challenge_data: String,
     pub challenge_hash: String,
+    /// Number of downloads after which the file gets deleted.
+    pub max_downloads: Option<u32>,
+    /// Requested expiry in seconds, the default expiry applies if missing.
+    pub expiry: Option<i64>,
 }
 
 /// Convert initial struct into finalized struct. Fails if a field is missing.
@@ -78,9 +104,10 @@ impl TryFrom<PartialUploadedFile> for UploadedFile {
         Ok(Self {
             iv: data.iv.ok_or(Error::FileDataConversionError("iv"))?,
             salt: data.salt.ok_or(Error::FileDataConversionError("salt"))?,
-            file_data: data
-                .file_data
+            file_size: data
+                .file_size
                 .ok_or(Error::FileDataConversionError("file_data"))?,
+            content_hash: data.content_hash,
             file_name_data: data
                 .file_name_data
                 .ok_or(Error::FileDataConversionError("file_name_data"))?,
@@ -90,6 +117,8 @@ impl TryFrom<PartialUploadedFile> for UploadedFile {
             challenge_hash: data
                 .challenge_hash
                 .ok_or(Error::FileDataConversionError("file_name_hash"))?,
+            max_downloads: data.max_downloads,
+            expiry: data.expiry,
         })
     }
 }
@@ -1,68 +1,321 @@
-use std::{future::ready, net::SocketAddr};
+use std::{future::ready, net::SocketAddr, sync::Arc, time::Duration};
 
-use axum::{routing::get, Router};
-use hdrop_shared::metrics::names;
+use axum::{
+    extract::State,
+    headers::{
+        authorization::{Basic, Bearer},
+        Authorization,
+    },
+    http::Request,
+    middleware::{from_fn_with_state, Next},
+    response::Response,
+    routing::get,
+    Router,
+    TypedHeader,
+};
+use hdrop_shared::{env, metrics::names};
 use metrics::{register_counter, register_gauge, register_histogram};
 use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 
-#[derive(Debug, Default)]
-pub struct PrometheusMetricsServer;
+use super::routes::verify_secret;
+use crate::{error::Error, Result};
+
+/// Time between two pushes to the Pushgateway, unless configured otherwise.
+const DEFAULT_PUSH_INTERVAL_SECS: u64 = 15;
+
+/// Decides where the `/metrics` endpoint is served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsMode {
+    /// Serve metrics on their own port, set via `METRICS_ADDRESS`.
+    Standalone,
+    /// Serve metrics on the main router, next to the API.
+    Main,
+    /// Do not serve metrics, e.g. if they are only pushed.
+    Disabled,
+}
+
+impl MetricsMode {
+    pub fn try_from_env() -> Result<Self> {
+        match env::metrics_mode().map(|s| s.to_lowercase()) {
+            Ok(ref mode) if mode == "standalone" => Ok(Self::Standalone),
+            Ok(ref mode) if mode == "main" => Ok(Self::Main),
+            Ok(ref mode) if mode == "disabled" => Ok(Self::Disabled),
+            Ok(mode) => Err(Error::InvalidMetricsMode(mode)),
+            Err(_) => Ok(Self::Standalone),
+        }
+    }
+}
+
+/// Credentials required to scrape the `/metrics` endpoint.
+#[derive(Debug)]
+struct MetricsAuth {
+    bearer_token: Option<String>,
+    basic: Option<(String, String)>,
+}
+
+impl MetricsAuth {
+    fn from_env() -> Self {
+        let basic = match (env::metrics_username(), env::metrics_password()) {
+            (Ok(username), Ok(password)) => Some((username, password)),
+            _ => None,
+        };
+
+        Self {
+            bearer_token: env::metrics_token().ok().filter(|token| !token.is_empty()),
+            basic,
+        }
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.bearer_token.is_some() || self.basic.is_some()
+    }
+}
+
+pub struct PrometheusMetricsServer {
+    handle: PrometheusHandle,
+    mode: MetricsMode,
+    auth: Arc<MetricsAuth>,
+}
 
 impl PrometheusMetricsServer {
-    /// Metrics Recorder.
-    fn metrics_router(&self) -> Router {
-        let recorder_handle = self.setup_metrics_recorder();
-        Router::new().route("/metrics", get(move || ready(recorder_handle.render())))
+    /// Install the metrics recorder and start pushing metrics, if `METRICS_PUSH_URL` is set.
+    /// Must only be called once.
+    pub fn try_from_env() -> Result<Self> {
+        let mode = MetricsMode::try_from_env()?;
+        let handle = Self::setup_metrics_recorder()?;
+
+        let auth = MetricsAuth::from_env();
+        if mode != MetricsMode::Disabled && !auth.is_enabled() {
+            tracing::warn!("Metrics are not protected, set METRICS_TOKEN or METRICS_USERNAME and METRICS_PASSWORD to require credentials");
+        }
+
+        Ok(Self {
+            handle,
+            mode,
+            auth: Arc::new(auth),
+        })
+    }
+
+    pub fn mode(&self) -> MetricsMode {
+        self.mode
+    }
+
+    /// Router serving the `/metrics` endpoint.
+    pub fn router(&self) -> Router {
+        let handle = self.handle.clone();
+        let router = Router::new().route("/metrics", get(move || ready(handle.render())));
+
+        match self.auth.is_enabled() {
+            true => router.route_layer(from_fn_with_state(self.auth.clone(), require_metrics_auth)),
+            false => router,
+        }
     }
 
     /// Metrics Setup.
     /// Set up all gauges and register them.
-    fn setup_metrics_recorder(&self) -> PrometheusHandle {
+    fn setup_metrics_recorder() -> Result<PrometheusHandle> {
         const EXPONENTIAL_SECONDS: &[f64] = &[
             0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
         ];
+        // 1 KiB up to 16 GiB
+        const EXPONENTIAL_BYTES: &[f64] = &[
+            1024.0,
+            16384.0,
+            262144.0,
+            1048576.0,
+            16777216.0,
+            268435456.0,
+            1073741824.0,
+            4294967296.0,
+            17179869184.0,
+        ];
+        // 1 second up to 1 week
+        const LONG_SECONDS: &[f64] = &[
+            1.0, 10.0, 60.0, 300.0, 900.0, 3600.0, 21600.0, 86400.0, 604800.0,
+        ];
 
-        let result = PrometheusBuilder::new()
+        let builder = PrometheusBuilder::new()
             .set_buckets_for_metric(
                 Matcher::Full(names::network::HTTP_REQUESTS_DURATION_SECONDS.to_string()),
                 EXPONENTIAL_SECONDS,
-            )
-            .unwrap()
-            .install_recorder()
-            .unwrap();
+            )?
+            .set_buckets_for_metric(
+                Matcher::Full(names::uploads::UPLOAD_SIZE_B.to_string()),
+                EXPONENTIAL_BYTES,
+            )?
+            .set_buckets_for_metric(
+                Matcher::Full(names::sync::LATENCY_SECONDS.to_string()),
+                LONG_SECONDS,
+            )?
+            .set_buckets_for_metric(
+                Matcher::Full(names::downloads::TIME_TO_FIRST_DOWNLOAD_SECONDS.to_string()),
+                LONG_SECONDS,
+            )?;
+
+        let handle = match env::metrics_push_url() {
+            // The exporter pushes the rendered metrics periodically, the handle still serves them for scraping
+            Ok(url) => {
+                let interval = Duration::from_secs(
+                    env::metrics_push_interval_secs().unwrap_or(DEFAULT_PUSH_INTERVAL_SECS),
+                );
+                let (recorder, exporter) = builder
+                    .with_push_gateway(
+                        &url,
+                        interval,
+                        env::metrics_push_username().ok(),
+                        env::metrics_push_password().ok(),
+                    )?
+                    .build()?;
+                let handle = recorder.handle();
+
+                metrics::set_boxed_recorder(Box::new(recorder))
+                    .map_err(metrics_exporter_prometheus::BuildError::from)?;
+                tokio::spawn(exporter);
 
-        self.register_metrics();
+                tracing::info!("Pushing metrics to {url} every {interval:?}");
+                handle
+            }
+            Err(_) => builder.install_recorder()?,
+        };
 
-        result
+        Self::register_metrics();
+
+        Ok(handle)
     }
 
     /// Register all gauges from names module.
-    fn register_metrics(&self) {
-        for name in names::GAUGE_NAMES {
+    fn register_metrics() {
+        for &name in names::GAUGE_NAMES {
             register_gauge!(name);
         }
-        for name in names::HISTOGRAM_NAMES {
+        for &name in names::HISTOGRAM_NAMES {
             register_histogram!(name);
         }
-        for name in names::COUNTER_NAMES {
+        for &name in names::COUNTER_NAMES {
             register_counter!(name);
         }
     }
 
-    /// Run the metrics server.
+    /// Run the metrics server on `METRICS_ADDRESS`.
     pub async fn run(self) {
-        let app = self.metrics_router();
-        let addr = SocketAddr::from(([0; 4], 3001));
+        let app = self.router();
+        let addr = env::metrics_address().unwrap_or(SocketAddr::from(([0; 4], 3001)));
 
         // Start the server
         tracing::info!("Prometheus exporter listening on {}", addr);
-        let server_result = axum::Server::bind(&addr)
-            .serve(app.into_make_service())
-            .await;
+        let server_result = match axum::Server::try_bind(&addr) {
+            Ok(server) => server.serve(app.into_make_service()).await,
+            Err(err) => Err(err),
+        };
 
-        // If the server fails to start, log the error
+        // If the server fails to start, log the error, the main server keeps running without it
         if let Err(err) = server_result {
             tracing::error!("Prometheus exporter failed to start: {}", err);
         }
     }
 }
+
+/// Middleware rejecting scrapes without the configured bearer token or basic auth credentials.
+async fn require_metrics_auth<B>(
+    State(auth): State<Arc<MetricsAuth>>,
+    bearer: Option<TypedHeader<Authorization<Bearer>>>,
+    basic: Option<TypedHeader<Authorization<Basic>>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    let bearer_valid = match (&auth.bearer_token, bearer) {
+        (Some(token), Some(TypedHeader(bearer))) => verify_secret(bearer.token(), token),
+        _ => false,
+    };
+    let basic_valid = match (&auth.basic, basic) {
+        (Some((username, password)), Some(TypedHeader(basic))) => {
+            // Both are compared, so the response time does not reveal a valid username
+            let username_valid = verify_secret(basic.username(), username);
+            verify_secret(basic.password(), password) && username_valid
+        }
+        _ => false,
+    };
+
+    match bearer_valid || basic_valid {
+        true => Ok(next.run(req).await),
+        false => Err(Error::MetricsAuth),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::{Method, StatusCode};
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    /// Request received by the stand-in Pushgateway.
+    struct Push {
+        method: Method,
+        credentials: Option<(String, String)>,
+        body: String,
+    }
+
+    /// Start an HTTP server on a random port which records every request.
+    fn start_gateway() -> (SocketAddr, mpsc::UnboundedReceiver<Push>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let gateway = Router::new().fallback(
+            move |method: Method,
+                  basic: Option<TypedHeader<Authorization<Basic>>>,
+                  body: String| {
+                let credentials = basic.map(|TypedHeader(basic)| {
+                    (basic.username().to_string(), basic.password().to_string())
+                });
+                let _ = sender.send(Push {
+                    method,
+                    credentials,
+                    body,
+                });
+                ready(StatusCode::OK)
+            },
+        );
+
+        let server = axum::Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .serve(gateway.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server);
+        (addr, receiver)
+    }
+
+    #[tokio::test]
+    async fn push_mode_sends_metrics_to_gateway() {
+        let (addr, mut pushes) = start_gateway();
+        std::env::set_var(
+            "METRICS_PUSH_URL",
+            format!("http://{addr}/metrics/job/hdrop"),
+        );
+        std::env::set_var("METRICS_PUSH_INTERVAL_SECS", "1");
+        std::env::set_var("METRICS_PUSH_USERNAME", "pusher");
+        std::env::set_var("METRICS_PUSH_PASSWORD", "push-secret");
+
+        // Installs the global recorder, so this is the only test doing so
+        PrometheusMetricsServer::try_from_env().unwrap();
+        metrics::increment_counter!(names::uploads::UPLOADS_TOTAL);
+
+        // The first push may happen before the counter was incremented
+        let push = tokio::time::timeout(Duration::from_secs(10), async {
+            loop {
+                let push = pushes.recv().await.expect("Gateway stopped");
+                if push
+                    .body
+                    .contains(&format!("{} 1", names::uploads::UPLOADS_TOTAL))
+                {
+                    return push;
+                }
+            }
+        })
+        .await
+        .expect("No metrics were pushed");
+
+        assert!(matches!(push.method, Method::PUT | Method::POST));
+        assert_eq!(
+            push.credentials,
+            Some(("pusher".to_string(), "push-secret".to_string()))
+        );
+    }
+}
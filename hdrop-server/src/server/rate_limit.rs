@@ -0,0 +1,269 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use hdrop_shared::{env, metrics::names};
+
+use super::app_state::AppState;
+use crate::{error::Error, Result};
+
+/// Requests per minute and client IP to files, unless configured otherwise.
+const DEFAULT_IP_LIMIT: u32 = 60;
+/// Requests per minute and access token, unless configured otherwise.
+const DEFAULT_ACCESS_TOKEN_LIMIT: u32 = 30;
+/// Header carrying the original client IP if the server runs behind a reverse proxy.
+const FORWARDED_FOR: &str = "x-forwarded-for";
+/// Time after a served range request during which further range requests of the client to the file are not charged.
+const STREAM_WINDOW: Duration = Duration::from_secs(60);
+
+/// Token bucket rate limiter.
+/// Every key may send a burst of `limit` requests, after which it is refilled evenly over a minute.
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    limit: f64,
+    buckets: Mutex<HashMap<K, Bucket>>,
+    last_prune: Mutex<Instant>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl<K: Hash + Eq> RateLimiter<K> {
+    const PERIOD: Duration = Duration::from_secs(60);
+
+    /// Create a limiter allowing `limit` requests per minute, `None` if the limit is 0.
+    pub fn new(limit: u32) -> Option<Self> {
+        (limit > 0).then(|| Self {
+            limit: limit as f64,
+            buckets: Mutex::default(),
+            last_prune: Mutex::new(Instant::now()),
+        })
+    }
+
+    fn refill_rate(&self) -> f64 {
+        self.limit / Self::PERIOD.as_secs_f64()
+    }
+
+    /// Take a token for the key. Returns the time until the next token is available if none is left.
+    pub fn check(&self, key: K) -> std::result::Result<(), Duration> {
+        let now = Instant::now();
+        self.prune(now);
+
+        let mut buckets = self.buckets.lock().expect("Rate limiter lock poisoned");
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: self.limit,
+            updated: now,
+        });
+
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_rate()).min(self.limit);
+        bucket.updated = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64(
+                (1.0 - bucket.tokens) / self.refill_rate(),
+            ))
+        }
+    }
+
+    /// Forget buckets which are full again, so the map does not grow with every client ever seen.
+    fn prune(&self, now: Instant) {
+        let mut last_prune = self.last_prune.lock().expect("Rate limiter lock poisoned");
+        if now.duration_since(*last_prune) < Self::PERIOD {
+            return;
+        }
+        *last_prune = now;
+
+        self.buckets
+            .lock()
+            .expect("Rate limiter lock poisoned")
+            .retain(|_, bucket| now.duration_since(bucket.updated) < Self::PERIOD);
+    }
+}
+
+/// Files which clients currently stream via range requests, e.g. for a video preview or a resumed download.
+/// Such streams easily send more range requests than the rate limits allow, so they are charged only once.
+#[derive(Debug)]
+struct RangeStreams {
+    /// Time of the last served range request by client IP and access token.
+    streams: Mutex<HashMap<(IpAddr, String), Instant>>,
+    last_prune: Mutex<Instant>,
+}
+
+impl RangeStreams {
+    fn new() -> Self {
+        Self {
+            streams: Mutex::default(),
+            last_prune: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Whether the client was served a range of the file recently.
+    fn is_active(&self, key: &(IpAddr, String)) -> bool {
+        self.streams
+            .lock()
+            .expect("Range streams lock poisoned")
+            .get(key)
+            .is_some_and(|served| served.elapsed() < STREAM_WINDOW)
+    }
+
+    /// Record a served range request, which keeps the stream active.
+    fn served(&self, key: (IpAddr, String)) {
+        let now = Instant::now();
+        self.prune(now);
+
+        self.streams
+            .lock()
+            .expect("Range streams lock poisoned")
+            .insert(key, now);
+    }
+
+    /// Forget streams which are inactive, so the map does not grow with every stream ever served.
+    fn prune(&self, now: Instant) {
+        let mut last_prune = self.last_prune.lock().expect("Range streams lock poisoned");
+        if now.duration_since(*last_prune) < STREAM_WINDOW {
+            return;
+        }
+        *last_prune = now;
+
+        self.streams
+            .lock()
+            .expect("Range streams lock poisoned")
+            .retain(|_, served| now.duration_since(*served) < STREAM_WINDOW);
+    }
+}
+
+/// Rate limits for requests to files, configured via the `RATE_LIMIT_*` environment variables.
+/// A limit of 0 disables the respective limiter.
+#[derive(Debug)]
+pub struct RateLimits {
+    per_ip: Option<RateLimiter<IpAddr>>,
+    per_access_token: Option<RateLimiter<String>>,
+    /// Number of reverse proxies in front of the server, each appending to the `X-Forwarded-For` header.
+    /// The client IP is taken from the header if at least one proxy is trusted.
+    trusted_proxies: usize,
+    range_streams: RangeStreams,
+}
+
+impl RateLimits {
+    pub fn from_env() -> Self {
+        Self {
+            per_ip: RateLimiter::new(env::rate_limit_ip_per_minute().unwrap_or(DEFAULT_IP_LIMIT)),
+            per_access_token: RateLimiter::new(
+                env::rate_limit_access_token_per_minute().unwrap_or(DEFAULT_ACCESS_TOKEN_LIMIT),
+            ),
+            trusted_proxies: Self::trusted_proxies_from_env(),
+            range_streams: RangeStreams::new(),
+        }
+    }
+
+    /// `RATE_LIMIT_TRUSTED_PROXIES` proxies, or a single one if only `RATE_LIMIT_TRUST_FORWARDED_FOR` is set.
+    fn trusted_proxies_from_env() -> usize {
+        env::rate_limit_trusted_proxies().unwrap_or_else(|_| {
+            match env::rate_limit_trust_forwarded_for().unwrap_or(false) {
+                true => 1,
+                false => 0,
+            }
+        })
+    }
+
+    /// IP of the client behind the trusted proxies.
+    ///
+    /// Clients can send any `X-Forwarded-For` header and every proxy appends the address it received the request from,
+    /// so only the entries appended by trusted proxies are reliable. Counted from the right, the entry added by the
+    /// outermost trusted proxy is the client IP. Requests which did not pass all trusted proxies are limited by their peer IP.
+    fn client_ip<B>(&self, req: &Request<B>, peer: SocketAddr) -> IpAddr {
+        if self.trusted_proxies == 0 {
+            return peer.ip();
+        }
+
+        // Proxies may append their entry as separate header
+        let entries = req
+            .headers()
+            .get_all(FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .collect::<Vec<_>>();
+
+        entries
+            .iter()
+            .rev()
+            .nth(self.trusted_proxies - 1)
+            .and_then(|ip| ip.trim().parse().ok())
+            .unwrap_or(peer.ip())
+    }
+}
+
+fn rejected(scope: &'static str, retry_after: Duration) -> Error {
+    metrics::increment_counter!(names::security::RATE_LIMITED_REQUESTS_TOTAL, "scope" => scope);
+    Error::RateLimited {
+        retry_after: retry_after.as_secs_f64().ceil() as u64,
+    }
+}
+
+/// Middleware limiting requests to files per client IP and per access token.
+/// Range requests of a client to a file it was served a range of recently are not charged,
+/// so streams are only charged for their first request. Only served ranges count, so requests failing
+/// the challenge are always charged.
+/// Must be used as route layer of routes with an `:access_token` parameter.
+pub async fn limit_file_requests<B>(
+    State(state): State<Arc<AppState>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Path(params): Path<HashMap<String, String>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    let limits = &state.rate_limits;
+    let client_ip = limits.client_ip(&req, peer);
+
+    let is_range = req.method() == Method::GET && req.headers().contains_key(header::RANGE);
+    let stream = params
+        .get("access_token")
+        .filter(|_| is_range)
+        .map(|access_token| (client_ip, access_token.clone()));
+    let streaming = stream
+        .as_ref()
+        .is_some_and(|stream| limits.range_streams.is_active(stream));
+
+    if !streaming {
+        if let Some(per_ip) = &limits.per_ip {
+            per_ip
+                .check(client_ip)
+                .map_err(|retry_after| rejected("ip", retry_after))?;
+        }
+
+        if let (Some(per_access_token), Some(access_token)) =
+            (&limits.per_access_token, params.get("access_token"))
+        {
+            per_access_token
+                .check(access_token.clone())
+                .map_err(|retry_after| rejected("access_token", retry_after))?;
+        }
+    }
+
+    let response = next.run(req).await;
+    if let Some(stream) = stream {
+        if response.status() == StatusCode::PARTIAL_CONTENT {
+            limits.range_streams.served(stream);
+        }
+    }
+
+    Ok(response)
+}
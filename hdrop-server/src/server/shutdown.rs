@@ -0,0 +1,63 @@
+use std::{sync::Arc, time::Duration};
+
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+use hdrop_shared::env;
+use tokio::signal;
+
+use super::app_state::AppState;
+use crate::{error::Error, Result};
+
+/// Time to keep serving requests after a shutdown signal while reporting not ready, unless configured otherwise.
+const DEFAULT_SHUTDOWN_DELAY_SECS: u64 = 5;
+/// Time to finish in-flight requests and sync jobs after the listener closed, unless configured otherwise.
+/// Together with the delay, stays below the default termination grace period of Kubernetes (30 seconds).
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 20;
+
+/// Time to keep accepting connections after a shutdown signal, configured via `SHUTDOWN_DELAY_SECS`.
+/// Readiness already fails meanwhile, so load balancers stop routing requests before the listener closes.
+pub fn delay() -> Duration {
+    Duration::from_secs(env::shutdown_delay_secs().unwrap_or(DEFAULT_SHUTDOWN_DELAY_SECS))
+}
+
+/// Time to drain the server after the listener closed, configured via `SHUTDOWN_TIMEOUT_SECS`.
+pub fn timeout() -> Duration {
+    Duration::from_secs(env::shutdown_timeout_secs().unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT_SECS))
+}
+
+/// Wait for SIGINT or SIGTERM.
+pub async fn signal() {
+    let interrupt = async {
+        signal::ctrl_c()
+            .await
+            .expect("Failed to install SIGINT handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("Failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+/// Middleware rejecting new uploads once the server is shutting down.
+/// Uploads which already started are finished.
+pub async fn reject_during_shutdown<B>(
+    State(state): State<Arc<AppState>>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response> {
+    if state.shutdown.is_cancelled() {
+        return Err(Error::ShuttingDown);
+    }
+
+    Ok(next.run(req).await)
+}
@@ -0,0 +1,248 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    io::SeekFrom,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use axum::body::Bytes;
+use futures::{Stream, StreamExt};
+use hdrop_shared::env;
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs,
+    io::{AsyncSeekExt, AsyncWriteExt},
+};
+use uuid::Uuid;
+
+use crate::{
+    core::{ByteRange, FileStream},
+    error::Error,
+    Result,
+};
+
+/// On-disk staging area for uploaded files.
+/// Files stay in the spool until they are synchronized with the storage provider,
+/// resumable uploads are assembled here chunk by chunk.
+/// Each file is stored in a single file named after its UUID.
+#[derive(Debug)]
+pub struct Spool {
+    dir: PathBuf,
+    active: Mutex<HashSet<Uuid>>,
+}
+
+/// Complete file written to the spool.
+#[derive(Debug)]
+pub struct SpooledFile {
+    pub size: u64,
+    /// Hex-encoded SHA-256 hash of the contents.
+    pub hash: String,
+}
+
+/// Result of writing a chunk to the spool.
+#[derive(Debug)]
+pub struct Appended {
+    /// Offset after the last byte written.
+    pub offset: u64,
+    /// Whether the chunk stream broke off before it was complete.
+    pub interrupted: bool,
+}
+
+impl Spool {
+    pub async fn try_from_env() -> Result<Self> {
+        let dir = Self::dir_from_env();
+        fs::create_dir_all(&dir).await?;
+
+        Ok(Self {
+            dir,
+            active: Mutex::default(),
+        })
+    }
+
+    pub fn dir_from_env() -> PathBuf {
+        env::upload_spool_dir().unwrap_or_else(|_| PathBuf::from("upload_spool"))
+    }
+
+    fn path(&self, key: Uuid) -> PathBuf {
+        self.dir.join(key.to_string())
+    }
+
+    /// Create an empty spool file.
+    pub async fn create(&self, key: Uuid) -> Result<()> {
+        fs::File::create(self.path(key)).await?;
+        Ok(())
+    }
+
+    /// Claim exclusive write access to a spool file.
+    /// Returns `None` if another request is currently writing to it.
+    pub fn lock(&self, key: Uuid) -> Option<SpoolGuard<'_>> {
+        let mut active = self.active.lock().expect("Spool lock poisoned");
+        active.insert(key).then(|| SpoolGuard { spool: self, key })
+    }
+
+    /// Stream a complete file into the spool, hashing it on the way.
+    pub async fn write<S, E>(&self, key: Uuid, max_length: u64, content: S) -> Result<SpooledFile>
+    where
+        S: Stream<Item = std::result::Result<Bytes, E>> + Unpin,
+        E: Display,
+    {
+        let mut hasher = Sha256::new();
+        let content = content.inspect(|data| {
+            if let Ok(data) = data {
+                hasher.update(data);
+            }
+        });
+
+        self.create(key).await?;
+        let appended = self.append(key, 0, max_length, content).await?;
+
+        if appended.interrupted {
+            self.delete(key).await?;
+            return Err(Error::FileUpload {
+                reason: "Upload interrupted".to_string(),
+            });
+        }
+
+        Ok(SpooledFile {
+            size: appended.offset,
+            hash: hex_digest(hasher),
+        })
+    }
+
+    /// Write a chunk to the spool file, starting at `offset`.
+    ///
+    /// Bytes beyond `offset` left behind by an interrupted request are discarded first.
+    /// If the chunk stream breaks off, everything received up to that point is kept,
+    /// so the client can resume from the returned offset.
+    pub async fn append<S, E>(
+        &self,
+        key: Uuid,
+        offset: u64,
+        max_length: u64,
+        mut chunk: S,
+    ) -> Result<Appended>
+    where
+        S: Stream<Item = std::result::Result<Bytes, E>> + Unpin,
+        E: Display,
+    {
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(self.path(key))
+            .await?;
+        file.set_len(offset).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+
+        let mut new_offset = offset;
+        let mut interrupted = false;
+        while let Some(data) = chunk.next().await {
+            let data = match data {
+                Ok(data) => data,
+                Err(err) => {
+                    tracing::debug!("Upload chunk for {key} interrupted at {new_offset}: {err}");
+                    interrupted = true;
+                    break;
+                }
+            };
+
+            if new_offset + data.len() as u64 > max_length {
+                file.set_len(offset).await?;
+                return Err(Error::UploadTooLarge);
+            }
+
+            file.write_all(&data).await?;
+            new_offset += data.len() as u64;
+        }
+
+        file.flush().await?;
+        file.sync_data().await?;
+
+        Ok(Appended {
+            offset: new_offset,
+            interrupted,
+        })
+    }
+
+    /// Open a spool file as a stream, optionally restricted to a range.
+    pub async fn open(&self, key: Uuid, range: Option<ByteRange>) -> Result<FileStream> {
+        let file = fs::File::open(self.path(key)).await?;
+        FileStream::from_file(file, range).await
+    }
+
+    /// Read the whole spool file.
+    pub async fn read(&self, key: Uuid) -> Result<Bytes> {
+        Ok(fs::read(self.path(key)).await?.into())
+    }
+
+    /// Hash a spool file, e.g. a resumable upload assembled from several chunks.
+    pub async fn hash(&self, key: Uuid) -> Result<String> {
+        let mut file = self.open(key, None).await?;
+        let mut hasher = Sha256::new();
+        while let Some(data) = file.stream.next().await {
+            hasher.update(data?);
+        }
+
+        Ok(hex_digest(hasher))
+    }
+
+    pub async fn exists(&self, key: Uuid) -> bool {
+        fs::try_exists(self.path(key)).await.unwrap_or(false)
+    }
+
+    /// List spool files which were not modified within the given duration.
+    /// Recently modified files may still be written and are left out.
+    pub async fn list_idle(&self, idle_for: Duration) -> Result<Vec<Uuid>> {
+        let mut keys = Vec::new();
+        let mut entries = fs::read_dir(&self.dir).await?;
+
+        while let Some(entry) = entries.next_entry().await? {
+            let Ok(key) = Uuid::parse_str(&entry.file_name().to_string_lossy()) else {
+                continue;
+            };
+
+            let modified = entry.metadata().await?.modified()?;
+            let idle = SystemTime::now()
+                .duration_since(modified)
+                .is_ok_and(|elapsed| elapsed >= idle_for);
+            if idle {
+                keys.push(key);
+            }
+        }
+
+        Ok(keys)
+    }
+
+    /// Delete a spool file. Missing files are ignored.
+    pub async fn delete(&self, key: Uuid) -> Result<()> {
+        match fs::remove_file(self.path(key)).await {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(err) => Err(Error::Io(err)),
+        }
+    }
+}
+
+fn hex_digest(hasher: Sha256) -> String {
+    hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Exclusive write access to a spool file, released on drop.
+pub struct SpoolGuard<'a> {
+    spool: &'a Spool,
+    key: Uuid,
+}
+
+impl Drop for SpoolGuard<'_> {
+    fn drop(&mut self) {
+        self.spool
+            .active
+            .lock()
+            .expect("Spool lock poisoned")
+            .remove(&self.key);
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+use hdrop_shared::env;
+use opentelemetry::{global, trace::TraceContextExt, KeyValue};
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+use tracing::Span;
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+use crate::{error::Error, Result};
+
+/// Key of the W3C trace context in a propagation carrier.
+const TRACEPARENT: &str = "traceparent";
+const DEFAULT_SERVICE_NAME: &str = "hdrop-server";
+
+/// Format of log lines written to stdout.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log collectors.
+    Json,
+}
+
+impl LogFormat {
+    pub fn try_from_env() -> Result<Self> {
+        match env::log_format().map(|s| s.to_lowercase()) {
+            Ok(ref format) if format == "text" => Ok(Self::Text),
+            Ok(ref format) if format == "json" => Ok(Self::Json),
+            Ok(format) => Err(Error::InvalidLogFormat(format)),
+            Err(_) => Ok(Self::Text),
+        }
+    }
+}
+
+/// Build a tracer exporting spans via OTLP/gRPC, if `OTEL_EXPORTER_OTLP_ENDPOINT` is set.
+/// Must be called within the Tokio runtime.
+fn otlp_tracer_from_env() -> Result<Option<trace::Tracer>> {
+    let Ok(endpoint) = env::otel_exporter_otlp_endpoint() else {
+        return Ok(None);
+    };
+    let service_name =
+        env::otel_service_name().unwrap_or_else(|_| DEFAULT_SERVICE_NAME.to_string());
+
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(
+            trace::config()
+                .with_resource(Resource::new([KeyValue::new("service.name", service_name)])),
+        )
+        .install_batch(runtime::Tokio)?;
+
+    Ok(Some(tracer))
+}
+
+/// Initialize the global tracing subscriber from env.
+pub fn setup_tracing() -> Result<()> {
+    let format = LogFormat::try_from_env()?;
+    let tracer = otlp_tracer_from_env()?;
+    init(format, tracer);
+    Ok(())
+}
+
+/// Initialize the global tracing subscriber, exporting spans with the given tracer.
+/// The tracer may use any exporter, e.g. an in-memory exporter to inspect spans in tests.
+pub fn init(format: LogFormat, tracer: Option<trace::Tracer>) {
+    // Trace contexts are stored with sync jobs in the W3C format
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let text = (format == LogFormat::Text).then(tracing_subscriber::fmt::layer);
+    let json = (format == LogFormat::Json).then(|| {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+    });
+    let otel = tracer.map(|tracer| tracing_opentelemetry::layer().with_tracer(tracer));
+
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "hdrop_server=debug,hdrop_db=debug,tower_http=debug".into()),
+        )
+        .with(text)
+        .with(json)
+        .with(otel)
+        .init();
+}
+
+/// Export spans which are not exported yet. Called before the process exits.
+pub fn shutdown() {
+    global::shutdown_tracer_provider();
+}
+
+/// Trace context of the current span, to be continued by background work.
+/// Returns `None` if spans are not exported.
+pub fn current_context() -> Option<String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&Span::current().context(), &mut carrier)
+    });
+    carrier.remove(TRACEPARENT)
+}
+
+/// Link the span to the trace context returned by [current_context].
+pub fn link(span: &Span, trace_context: &str) {
+    let carrier = HashMap::from([(TRACEPARENT.to_string(), trace_context.to_string())]);
+    let context = global::get_text_map_propagator(|propagator| propagator.extract(&carrier));
+    let span_context = context.span().span_context().clone();
+
+    if span_context.is_valid() {
+        span.add_link(span_context);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry_sdk::testing::trace::InMemorySpanExporter;
+    use tracing_subscriber::Registry;
+
+    use super::*;
+
+    #[test]
+    fn link_points_to_current_context() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let exporter = InMemorySpanExporter::default();
+        let provider = trace::TracerProvider::builder()
+            .with_simple_exporter(exporter.clone())
+            .build();
+        let subscriber = Registry::default()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let trace_context = tracing::info_span!("upload")
+                .in_scope(current_context)
+                .expect("Spans are exported, so there is a trace context");
+
+            // Like a sync job, the linked span starts a trace of its own
+            let sync_job = tracing::info_span!(parent: None, "sync_job");
+            link(&sync_job, &trace_context);
+        });
+        provider.force_flush();
+
+        let spans = exporter.get_finished_spans().unwrap();
+        let span = |name: &str| {
+            spans
+                .iter()
+                .find(|span| span.name == name)
+                .unwrap_or_else(|| panic!("Span {name} was not exported"))
+        };
+        let (upload, sync_job) = (span("upload"), span("sync_job"));
+
+        assert_ne!(
+            sync_job.span_context.trace_id(),
+            upload.span_context.trace_id()
+        );
+        assert!(sync_job
+            .links
+            .iter()
+            .any(|link| link.span_context == upload.span_context));
+    }
+}
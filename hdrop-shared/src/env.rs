@@ -1,4 +1,4 @@
-use std::path::PathBuf;
+use std::{net::SocketAddr, path::PathBuf, str::FromStr};
 
 use once_cell::sync::OnceCell;
 use paste::paste;
@@ -43,29 +43,86 @@ macro_rules! env_get {
     };
 }
 
+/// Get the value of a setting of a named instance, e.g. of one of several storage providers.
+/// Instances read their settings with the upper-cased instance name as prefix,
+/// e.g. `BACKUP_S3_REGION` for the `S3_REGION` setting of the instance `backup`.
+/// Without an instance name, the variable is read without prefix.
+pub fn instance_var<T: FromStr>(instance: Option<&str>, key: &str) -> Result<T, EnvError> {
+    let key = match instance {
+        Some(instance) => format!("{}_{key}", instance.to_uppercase()),
+        None => key.to_string(),
+    };
+
+    std::env::var(&key)
+        .map_err(|_| EnvError::KeyNotFound { key: key.clone() })?
+        .parse()
+        .map_err(|_| EnvError::ParseError { key })
+}
+
 // Server
 env_get!(port => u16);
 env_get!(cors_origin);
 env_get!(single_file_limit_mb => usize);
 env_get!(storage_provider);
+env_get!(storage_replication_policy);
+env_get!(storage_write_quorum => usize);
+env_get!(upload_spool_dir => PathBuf);
+env_get!(deduplication => bool);
+env_get!(shutdown_timeout_secs => u64);
+env_get!(shutdown_delay_secs => u64);
+env_get!(reconciliation_policy);
+env_get!(reconciliation_interval_minutes => u64);
+env_get!(reconciliation_delete_orphans => bool);
+env_get!(metrics_interval_secs => u64);
+
+// Metrics
+env_get!(metrics_mode);
+env_get!(metrics_address => SocketAddr);
+env_get!(metrics_token);
+env_get!(metrics_username);
+env_get!(metrics_password);
+env_get!(metrics_push_url);
+env_get!(metrics_push_interval_secs => u64);
+env_get!(metrics_push_username);
+env_get!(metrics_push_password);
+
+// Tracing
+env_get!(log_format);
+env_get!(otel_exporter_otlp_endpoint);
+env_get!(otel_service_name);
+
+// Health
+env_get!(health_cache_max_usage_percent => f64);
+env_get!(health_sync_max_queued => i64);
+env_get!(health_sync_max_age_secs => i64);
+
+// Admin
+env_get!(admin_address => SocketAddr);
+env_get!(admin_token);
+
+// Expiry
+env_get!(expiry_default => i64);
+env_get!(expiry_min => i64);
+env_get!(expiry_max => i64);
+env_get!(expiry_tiers);
+
+// Brute-force protection
+env_get!(rate_limit_ip_per_minute => u32);
+env_get!(rate_limit_access_token_per_minute => u32);
+env_get!(rate_limit_trust_forwarded_for => bool);
+env_get!(rate_limit_trusted_proxies => usize);
+env_get!(challenge_lockout_attempts => u32);
 
 // Database
 env_get!(database_url);
+env_get!(token_alphabet);
+env_get!(token_entropy_source);
+env_get!(access_token_min_length => usize);
+env_get!(update_token_length => usize);
 
 // Cache
 env_get!(cache_strategy);
 env_get!(cache_memory_limit_mb => usize);
 env_get!(cache_disk_limit_mb => usize);
+env_get!(cache_entry_limit_mb => usize);
 env_get!(cache_dir => PathBuf);
-
-// S3 Provider
-env_get!(s3_region);
-env_get!(s3_endpoint);
-env_get!(s3_access_key_id);
-env_get!(s3_secret_access_key);
-env_get!(s3_bucket_name);
-env_get!(s3_public_url);
-
-// Local Provider
-env_get!(local_storage_dir => PathBuf);
-env_get!(local_storage_limit_mb => usize);
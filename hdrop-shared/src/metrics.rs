@@ -8,16 +8,53 @@ pub trait UpdateMetrics {
 }
 
 pub mod names {
-    pub const GAUGE_NAMES: [&str; 4] = [
+    pub const GAUGE_NAMES: &[&str] = &[
         storage::USED_STORAGE_B,
         storage::CACHE_TOTAL_CAPACITY_B,
         storage::CACHE_USED_CAPACITY_B,
         storage::DATABASE_FILE_COUNT,
+        migration::FILES_TOTAL,
+        migration::FILES_MIGRATED,
+        migration::FILES_FAILED,
+        migration::COPIED_B,
+        sync::QUEUE_DEPTH,
+        sync::OLDEST_JOB_AGE_SECONDS,
+        expiration::LAST_SWEEP_FILES_EXPIRED,
+        network::NETWORK_OCTETS_RECEIVED,
+        network::NETWORK_OCTETS_TRANSMITTED,
+        system::AVG_CPU_USAGE,
+        system::RAM_USAGE_B,
+        system::DISK_TOTAL_B,
+        system::DISK_USED_B,
+        process::RESIDENT_MEMORY_B,
+        process::OPEN_FDS,
+        process::TOKIO_WORKERS,
+        process::TOKIO_ALIVE_TASKS,
     ];
 
-    pub const HISTOGRAM_NAMES: [&str; 1] = [network::HTTP_REQUESTS_DURATION_SECONDS];
+    pub const HISTOGRAM_NAMES: &[&str] = &[
+        network::HTTP_REQUESTS_DURATION_SECONDS,
+        uploads::UPLOAD_SIZE_B,
+        downloads::TIME_TO_FIRST_DOWNLOAD_SECONDS,
+        sync::LATENCY_SECONDS,
+    ];
 
-    pub const COUNTER_NAMES: [&str; 1] = [network::HTTP_REQUESTS_TOTAL];
+    pub const COUNTER_NAMES: &[&str] = &[
+        network::HTTP_REQUESTS_TOTAL,
+        uploads::UPLOADS_TOTAL,
+        uploads::UPLOADED_B_TOTAL,
+        downloads::DOWNLOADS_TOTAL,
+        downloads::DOWNLOADED_B_TOTAL,
+        downloads::CACHE_REQUESTS_TOTAL,
+        security::CHALLENGES_TOTAL,
+        expiration::FILES_EXPIRED_TOTAL,
+        sync::RETRIES_TOTAL,
+        security::RATE_LIMITED_REQUESTS_TOTAL,
+        security::FAILED_CHALLENGES_TOTAL,
+        security::LOCKED_OUT_REQUESTS_TOTAL,
+        reconciliation::FINDINGS_TOTAL,
+        reconciliation::ACTIONS_TOTAL,
+    ];
 
     /// Server requests, latency
     /// Network interface
@@ -36,8 +73,70 @@ pub mod names {
         pub const DATABASE_FILE_COUNT: &str = "database_file_count";
     }
 
+    /// Files received from clients
+    pub mod uploads {
+        pub const UPLOADS_TOTAL: &str = "uploads_total";
+        pub const UPLOAD_SIZE_B: &str = "upload_size_bytes";
+        pub const UPLOADED_B_TOTAL: &str = "uploaded_bytes_total";
+    }
+
+    /// Files served to clients
+    pub mod downloads {
+        pub const DOWNLOADS_TOTAL: &str = "downloads_total";
+        pub const DOWNLOADED_B_TOTAL: &str = "downloaded_bytes_total";
+        pub const CACHE_REQUESTS_TOTAL: &str = "download_cache_requests_total";
+        pub const TIME_TO_FIRST_DOWNLOAD_SECONDS: &str = "time_to_first_download_seconds";
+    }
+
+    /// Files deleted by the expiration worker
+    pub mod expiration {
+        pub const FILES_EXPIRED_TOTAL: &str = "expired_files_total";
+        pub const LAST_SWEEP_FILES_EXPIRED: &str = "expiration_last_sweep_files";
+    }
+
+    /// Progress of a storage migration
+    pub mod migration {
+        pub const FILES_TOTAL: &str = "storage_migration_files_total";
+        pub const FILES_MIGRATED: &str = "storage_migration_files_migrated";
+        pub const FILES_FAILED: &str = "storage_migration_files_failed";
+        pub const COPIED_B: &str = "storage_migration_copied_bytes";
+    }
+
+    /// Queue of files waiting to be stored on the storage provider
+    pub mod sync {
+        pub const QUEUE_DEPTH: &str = "storage_sync_queue_depth";
+        pub const OLDEST_JOB_AGE_SECONDS: &str = "storage_sync_oldest_job_age_seconds";
+        pub const LATENCY_SECONDS: &str = "storage_sync_latency_seconds";
+        pub const RETRIES_TOTAL: &str = "storage_sync_retries_total";
+    }
+
+    /// Inconsistencies found between database, cache, spool and storage provider
+    pub mod reconciliation {
+        pub const FINDINGS_TOTAL: &str = "reconciliation_findings_total";
+        pub const ACTIONS_TOTAL: &str = "reconciliation_actions_total";
+    }
+
+    /// Challenges and rejected attempts to access files
+    pub mod security {
+        pub const RATE_LIMITED_REQUESTS_TOTAL: &str = "rate_limited_requests_total";
+        pub const CHALLENGES_TOTAL: &str = "challenges_total";
+        pub const FAILED_CHALLENGES_TOTAL: &str = "failed_challenges_total";
+        pub const LOCKED_OUT_REQUESTS_TOTAL: &str = "locked_out_requests_total";
+    }
+
+    /// Whole system: CPU, RAM, disks
     pub mod system {
         pub const AVG_CPU_USAGE: &str = "avg_cpu_usage";
         pub const RAM_USAGE_B: &str = "ram_usage_bytes";
+        pub const DISK_TOTAL_B: &str = "disk_total_bytes";
+        pub const DISK_USED_B: &str = "disk_used_bytes";
+    }
+
+    /// Server process and its async runtime
+    pub mod process {
+        pub const RESIDENT_MEMORY_B: &str = "process_resident_memory_bytes";
+        pub const OPEN_FDS: &str = "process_open_fds";
+        pub const TOKIO_WORKERS: &str = "tokio_workers";
+        pub const TOKIO_ALIVE_TASKS: &str = "tokio_alive_tasks";
     }
 }
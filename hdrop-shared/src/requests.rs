@@ -1,5 +1,11 @@
 mod challenge_data;
+mod create_upload_data;
+mod download_limit_data;
 mod expiry_data;
+mod storage_migration_data;
 
 pub use challenge_data::ChallengeData;
+pub use create_upload_data::CreateUploadData;
+pub use download_limit_data::DownloadLimitData;
 pub use expiry_data::ExpiryData;
+pub use storage_migration_data::StorageMigrationData;
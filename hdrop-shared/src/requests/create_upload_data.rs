@@ -0,0 +1,13 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct CreateUploadData {
+    pub iv: String,
+    pub salt: String,
+    pub file_name_data: String,
+    pub challenge_data: String,
+    pub challenge_hash: String,
+    pub max_downloads: Option<u32>,
+    /// Requested expiry of the finished file in seconds.
+    pub expiry: Option<i64>,
+}
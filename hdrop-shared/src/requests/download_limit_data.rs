@@ -0,0 +1,7 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct DownloadLimitData {
+    /// Number of downloads after which the file gets deleted, `None` removes the limit.
+    pub max_downloads: Option<u32>,
+}
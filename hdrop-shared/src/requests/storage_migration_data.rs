@@ -0,0 +1,14 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct StorageMigrationData {
+    /// Provider to copy files from, named like in `STORAGE_PROVIDER`.
+    pub source: String,
+    /// Provider to copy files to, named like in `STORAGE_PROVIDER`.
+    pub target: String,
+    /// Maximum number of files copied per second, unlimited if missing.
+    pub files_per_second: Option<f64>,
+    /// Discard the saved progress and start with the first file again.
+    #[serde(default)]
+    pub reset: bool,
+}
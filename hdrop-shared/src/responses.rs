@@ -1,12 +1,28 @@
 use serde::Serialize;
+mod admin_file_data;
+mod cache_data;
+mod expiry_policy_data;
 mod file_metadata;
 mod get_challenge_data;
+mod health_data;
+mod server_config_data;
+mod storage_migration_data;
+mod sync_queue_data;
 mod upload_file_data;
+mod upload_session_data;
 mod verify_challenge_data;
 
+pub use admin_file_data::{AdminFileData, SyncState};
+pub use cache_data::{CacheStatusData, FlushCacheData};
+pub use expiry_policy_data::{ExpiryPolicyData, ExpiryTierData};
 pub use file_metadata::FileMetaData;
 pub use get_challenge_data::GetChallengeData;
+pub use health_data::{ComponentHealthData, HealthData, HealthStatus};
+pub use server_config_data::{FeaturesData, FileDelivery, ServerConfigData, StorageConfigData};
+pub use storage_migration_data::StorageMigrationStatusData;
+pub use sync_queue_data::{SyncJobData, SyncQueueData};
 pub use upload_file_data::UploadFileData;
+pub use upload_session_data::UploadSessionData;
 pub use verify_challenge_data::VerifyChallengeData;
 
 #[derive(Debug, Serialize)]
@@ -0,0 +1,29 @@
+use serde::Serialize;
+
+/// File as listed by the admin API.
+#[derive(Debug, Serialize)]
+pub struct AdminFileData {
+    pub uuid: String,
+    pub access_token: String,
+    /// Size of the file contents in bytes, unknown for files uploaded before it was recorded.
+    pub file_size: Option<i64>,
+    /// Time since the upload in seconds.
+    pub age_seconds: i64,
+    /// Time until the file expires in seconds, negative if it awaits deletion.
+    pub expires_in_seconds: i64,
+    pub download_count: i32,
+    /// Whether the file contents are cached.
+    pub cached: bool,
+    pub sync_state: SyncState,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyncState {
+    /// The file is stored on the storage provider.
+    Synced,
+    /// The file waits for its first synchronization.
+    Queued,
+    /// Synchronizing the file failed, it is retried later.
+    Retrying,
+}
@@ -0,0 +1,19 @@
+use serde::Serialize;
+
+/// Status of the file cache.
+#[derive(Debug, Serialize)]
+pub struct CacheStatusData {
+    /// Cache strategy, e.g. `memory` or `hybrid`.
+    pub strategy: String,
+    /// Capacity of the cache in bytes, `None` if unlimited.
+    pub total_bytes: Option<u64>,
+    /// Number of bytes currently stored in the cache.
+    pub used_bytes: Option<u64>,
+}
+
+/// Result of flushing the file cache.
+#[derive(Debug, Serialize)]
+pub struct FlushCacheData {
+    /// Number of cache entries deleted.
+    pub deleted: usize,
+}
@@ -0,0 +1,19 @@
+use serde::Serialize;
+
+/// Expiries accepted by the server, all values in seconds.
+#[derive(Debug, Serialize, Clone)]
+pub struct ExpiryPolicyData {
+    /// Expiry of files uploaded without a requested expiry.
+    pub default_expiry: i64,
+    pub min_expiry: i64,
+    pub max_expiry: i64,
+    /// Lower maximum expiries for large files, ordered by file size.
+    pub tiers: Vec<ExpiryTierData>,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct ExpiryTierData {
+    /// Files larger than this number of bytes may not exceed `max_expiry`.
+    pub min_file_size: u64,
+    pub max_expiry: i64,
+}
@@ -5,4 +5,6 @@ pub struct GetChallengeData {
     pub salt: String,
     pub iv: String,
     pub challenge: String,
+    /// Number of downloads left, `None` if unlimited.
+    pub remaining_downloads: Option<u32>,
 }
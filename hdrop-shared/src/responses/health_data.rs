@@ -0,0 +1,40 @@
+use std::collections::BTreeMap;
+
+use serde::Serialize;
+
+/// Readiness of the server and the components it depends on.
+#[derive(Debug, Serialize)]
+pub struct HealthData {
+    /// Worst status of all components.
+    pub status: HealthStatus,
+    /// Components by name, e.g. `database` or `storage`.
+    pub components: BTreeMap<&'static str, ComponentHealthData>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentHealthData {
+    pub status: HealthStatus,
+    /// Reason for a status other than `ok`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl From<HealthStatus> for ComponentHealthData {
+    fn from(status: HealthStatus) -> Self {
+        Self {
+            status,
+            message: None,
+        }
+    }
+}
+
+/// Ordered from best to worst.
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    Ok,
+    /// The component works, but needs attention, e.g. a full cache.
+    Degraded,
+    /// The component does not work, the server cannot serve requests.
+    Down,
+}
@@ -0,0 +1,46 @@
+use serde::Serialize;
+
+use super::ExpiryPolicyData;
+
+/// Configuration and capabilities of the server, so clients do not have to hardcode them.
+#[derive(Debug, Serialize, Clone)]
+pub struct ServerConfigData {
+    /// Version of the API, e.g. `v1`.
+    pub api_version: String,
+    /// Version of the server.
+    pub server_version: String,
+    /// Maximum size of a single file.
+    pub single_file_limit_mb: usize,
+    pub expiry: ExpiryPolicyData,
+    pub storage: StorageConfigData,
+    pub features: FeaturesData,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct StorageConfigData {
+    /// Kind of the primary storage provider, e.g. `s3` or `filesystem`.
+    pub provider: String,
+    /// How stored files are delivered on download.
+    pub file_delivery: FileDelivery,
+}
+
+#[derive(Debug, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileDelivery {
+    /// File contents are sent in the response.
+    Bytes,
+    /// A download URL is sent instead of the file contents, unless the file is still cached by the server.
+    Url,
+}
+
+#[derive(Debug, Serialize, Clone)]
+pub struct FeaturesData {
+    /// Downloads support the `Range` header.
+    pub range_requests: bool,
+    /// Files can be uploaded in chunks via `/files/uploads`.
+    pub resumable_uploads: bool,
+    /// Files can be deleted after a number of downloads.
+    pub download_limits: bool,
+    /// Identical files are stored only once.
+    pub deduplication: bool,
+}
@@ -0,0 +1,16 @@
+use serde::Serialize;
+
+/// Progress of a storage migration.
+#[derive(Debug, Serialize)]
+pub struct StorageMigrationStatusData {
+    pub source: String,
+    pub target: String,
+    /// Whether the migration is currently running on this instance.
+    pub running: bool,
+    /// Number of files copied so far.
+    pub migrated: i64,
+    /// Number of files which could not be copied yet, they are retried.
+    pub retrying: usize,
+    /// Time since the last progress in seconds.
+    pub updated_seconds_ago: i64,
+}
@@ -0,0 +1,24 @@
+use serde::Serialize;
+
+/// Status of the storage synchronization queue.
+#[derive(Debug, Serialize)]
+pub struct SyncQueueData {
+    /// Number of files waiting to be stored on the storage provider.
+    pub queued: i64,
+    /// Age of the oldest queued job in seconds.
+    pub oldest_job_age_seconds: Option<i64>,
+    /// Queued jobs in order of their next attempt.
+    pub jobs: Vec<SyncJobData>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SyncJobData {
+    pub uuid: String,
+    /// Number of failed attempts so far.
+    pub attempts: i32,
+    /// Time until the next attempt in seconds, negative if it is due.
+    pub next_attempt_in_seconds: i64,
+    /// Whether a worker is currently synchronizing the file.
+    pub leased: bool,
+    pub last_error: Option<String>,
+}
@@ -4,4 +4,6 @@ use serde::Serialize;
 pub struct UploadFileData {
     pub access_token: String,
     pub update_token: String,
+    /// Number of downloads left, `None` if unlimited.
+    pub remaining_downloads: Option<u32>,
 }
@@ -0,0 +1,8 @@
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct UploadSessionData {
+    pub upload_id: String,
+    pub upload_offset: u64,
+    pub upload_length: u64,
+}